@@ -6,75 +6,192 @@ pub enum Atom {
     // Keyword(String),
     Str(String),
     Arr(Vec<Atom>),
+    /// Key-value pairs, kept sorted by key with `key_cmp` so equal maps compare equal
+    Map(Vec<(Atom, Atom)>),
     BuiltIn(BuiltIn),
 }
 
+/// Total order of map keys: by type first, then by value
+pub fn key_cmp(a: &Atom, b: &Atom) -> std::cmp::Ordering {
+    fn rank(atom: &Atom) -> u8 {
+        match atom {
+            Atom::Float(_) => 0,
+            Atom::Str(_) => 1,
+            Atom::Arr(_) => 2,
+            Atom::Map(_) => 3,
+            Atom::BuiltIn(_) => 4,
+        }
+    }
+    fn all_cmp<'a>(a: impl Iterator<Item = &'a Atom>, b: impl Iterator<Item = &'a Atom>) -> std::cmp::Ordering {
+        let (a, b): (Vec<_>, Vec<_>) = (a.collect(), b.collect());
+        a.iter().zip(&b).map(|(x, y)| key_cmp(x, y)).find(|o| o.is_ne()).unwrap_or(a.len().cmp(&b.len()))
+    }
+
+    match (a, b) {
+        (Atom::Float(x), Atom::Float(y)) => x.total_cmp(y),
+        (Atom::Str(x), Atom::Str(y)) => x.cmp(y),
+        (Atom::Arr(x), Atom::Arr(y)) => all_cmp(x.iter(), y.iter()),
+        (Atom::Map(x), Atom::Map(y)) => {
+            all_cmp(x.iter().flat_map(|(k, v)| vec![k, v]), y.iter().flat_map(|(k, v)| vec![k, v]))
+        }
+        (Atom::BuiltIn(x), Atom::BuiltIn(y)) => x.def().symbol.cmp(y.def().symbol),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// Set `key` to `value` in `map`, keeping it sorted
+pub fn map_insert(map: &mut Vec<(Atom, Atom)>, key: Atom, value: Atom) {
+    match map.binary_search_by(|(k, _)| key_cmp(k, &key)) {
+        Ok(i) => map[i].1 = value,
+        Err(i) => map.insert(i, (key, value)),
+    }
+}
+
+/// Where `key` is in `map`, if it is there
+fn map_find(map: &[(Atom, Atom)], key: &Atom) -> Option<usize> {
+    map.binary_search_by(|(k, _)| key_cmp(k, key)).ok()
+}
+
 impl std::ops::Add for Atom {
-    type Output = Self;
-    fn add(self, rhs: Atom) -> Self {
+    type Output = Result<Self, RuntimeErrorKind>;
+    fn add(self, rhs: Atom) -> Self::Output {
         match (self, rhs) {
-            (Atom::Float(lhs), Atom::Float(rhs)) => Atom::Float(lhs + rhs),
-            (Atom::Str(lhs), Atom::Str(rhs)) => Atom::Str(format!("{}{}", lhs, rhs)),
-            (_, _) => panic!("I can't add those types"),
+            (Atom::Float(lhs), Atom::Float(rhs)) => Ok(Atom::Float(lhs + rhs)),
+            (Atom::Str(lhs), Atom::Str(rhs)) => Ok(Atom::Str(format!("{}{}", lhs, rhs))),
+            (_, _) => Err(RuntimeErrorKind::Type("I can't add those types".to_string())),
         }
     }
 }
 
 impl std::ops::Sub for Atom {
-    type Output = Self;
-    fn sub(self, rhs: Atom) -> Self {
+    type Output = Result<Self, RuntimeErrorKind>;
+    fn sub(self, rhs: Atom) -> Self::Output {
         match (self, rhs) {
-            (Atom::Float(lhs), Atom::Float(rhs)) => Atom::Float(lhs - rhs),
-            (Atom::Str(lhs), Atom::Str(rhs)) => Atom::Str(format!("{}{}", lhs, rhs)),
-            (_, _) => panic!("I can't subtract those types"),
+            (Atom::Float(lhs), Atom::Float(rhs)) => Ok(Atom::Float(lhs - rhs)),
+            (Atom::Str(lhs), Atom::Str(rhs)) => Ok(Atom::Str(format!("{}{}", lhs, rhs))),
+            (_, _) => Err(RuntimeErrorKind::Type("I can't subtract those types".to_string())),
         }
     }
 }
 
 impl std::ops::Mul for Atom {
-    type Output = Self;
-    fn mul(self, rhs: Atom) -> Self {
+    type Output = Result<Self, RuntimeErrorKind>;
+    fn mul(self, rhs: Atom) -> Self::Output {
         match (self, rhs) {
-            (Atom::Float(lhs), Atom::Float(rhs)) => Atom::Float(lhs * rhs),
+            (Atom::Float(lhs), Atom::Float(rhs)) => Ok(Atom::Float(lhs * rhs)),
             // (Atom::Str(lhs), Atom::Str(rhs)) => Atom::Str(format!("{}{}", lhs, rhs)),
-            (_, _) => panic!("I can't multiply those types"),
+            (_, _) => Err(RuntimeErrorKind::Type("I can't multiply those types".to_string())),
         }
     }
 }
 
 impl std::ops::Div for Atom {
-    type Output = Self;
-    fn div(self, rhs: Atom) -> Self {
+    type Output = Result<Self, RuntimeErrorKind>;
+    fn div(self, rhs: Atom) -> Self::Output {
         match (self, rhs) {
-            (Atom::Float(lhs), Atom::Float(rhs)) => Atom::Float(lhs / rhs),
+            (Atom::Float(lhs), Atom::Float(rhs)) => Ok(Atom::Float(lhs / rhs)),
             // (Atom::Str(lhs), Atom::Str(rhs)) => Atom::Str(format!("{}{}", lhs, rhs)),
-            (_, _) => panic!("I can't divide those types"),
+            (_, _) => Err(RuntimeErrorKind::Type("I can't divide those types".to_string())),
         }
     }
 }
 
 impl Atom {
-    fn pow(self, rhs: Atom) -> Self {
+    /// How many characters or atoms this holds, counting nested arrays
+    pub fn size(&self) -> usize {
+        match self {
+            Atom::Str(st) => st.chars().count(),
+            Atom::Arr(arr) => arr.iter().map(|atom| atom.size().max(1)).sum(),
+            Atom::Map(map) => map.iter().map(|(k, v)| k.size().max(1) + v.size().max(1)).sum(),
+            _ => 1,
+        }
+    }
+
+    /// This atom as JSON: numbers, strings and arrays as they are, maps as objects and
+    /// builtins as their symbol.
+    /// Numbers JSON can't hold, like `inf`, become `null`.
+    pub fn to_json(&self) -> serde_json::Value {
+        use serde_json::Value;
+        match self {
+            // whole numbers are written without a `.0`, as far as they stay exact
+            Atom::Float(f) if f.fract() == 0.0 && f.abs() < (1u64 << 53) as Float => Value::from(*f as i64),
+            Atom::Float(f) => serde_json::Number::from_f64(*f).map_or(Value::Null, Value::Number),
+            Atom::Str(st) => Value::String(st.clone()),
+            Atom::Arr(arr) => Value::Array(arr.iter().map(Atom::to_json).collect()),
+            // JSON keys are always strings
+            Atom::Map(map) => Value::Object(map.iter().map(|(k, v)| (k.to_string(), v.to_json())).collect()),
+            Atom::BuiltIn(bi) => Value::String(bi.def().symbol.to_string()),
+        }
+    }
+
+    /// Turn a JSON document into atoms. `true`, `false` and `null` become `1`, `0` and `0`,
+    /// objects become maps with string keys.
+    pub fn from_json(value: &serde_json::Value) -> Atom {
+        use serde_json::Value;
+        match value {
+            Value::Null => Atom::Float(0.0),
+            Value::Bool(b) => Atom::Float(if *b { 1.0 } else { 0.0 }),
+            Value::Number(n) => Atom::Float(n.as_f64().unwrap_or(Float::NAN)),
+            Value::String(st) => Atom::Str(st.clone()),
+            Value::Array(arr) => Atom::Arr(arr.iter().map(Atom::from_json).collect()),
+            Value::Object(obj) => {
+                let mut map = Vec::new();
+                for (k, v) in obj {
+                    map_insert(&mut map, Atom::Str(k.clone()), Atom::from_json(v));
+                }
+                Atom::Map(map)
+            }
+        }
+    }
+
+    /// Name of this atom's type, as shown to users
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Atom::Float(_) => "float",
+            Atom::Str(_) => "str",
+            Atom::Arr(_) => "arr",
+            Atom::Map(_) => "map",
+            Atom::BuiltIn(_) => "builtin",
+        }
+    }
+
+    /// This atom the way it would be written in source, keeping its type visible: strings
+    /// are quoted with escapes, arrays bracketed, maps in `{key value}` form and builtins
+    /// shown as their symbol. Finite numbers, strings, builtins and maps of numbers and
+    /// strings parse back; arrays (there are no array literals yet), NaN, infinities and
+    /// maps holding any of those don't.
+    pub fn repr(&self) -> String {
+        let join = |atoms: Vec<&Atom>| atoms.iter().map(|atom| atom.repr()).collect::<Vec<String>>().join(" ");
+        match self {
+            Atom::Float(f) => f.to_string(),
+            Atom::Str(st) => lexer::escape(st),
+            Atom::Arr(arr) => format!("[{}]", join(arr.iter().collect())),
+            Atom::Map(map) => format!("{{{}}}", join(map.iter().flat_map(|(k, v)| vec![k, v]).collect())),
+            Atom::BuiltIn(bi) => bi.def().symbol.to_string(),
+        }
+    }
+
+    fn pow(self, rhs: Atom) -> Result<Self, RuntimeErrorKind> {
         match (self, rhs) {
-            (Atom::Float(lhs), Atom::Float(rhs)) => Atom::Float(lhs.powf(rhs)),
+            (Atom::Float(lhs), Atom::Float(rhs)) => Ok(Atom::Float(lhs.powf(rhs))),
             // (Atom::Str(lhs), Atom::Str(rhs)) => Atom::Str(format!("{}{}", lhs, rhs)),
-            (_, _) => panic!("I can't raise those types"),
+            (_, _) => Err(RuntimeErrorKind::Type("I can't raise those types".to_string())),
         }
     }
 
-    fn modu(self, rhs: Atom) -> Self {
+    fn modu(self, rhs: Atom) -> Result<Self, RuntimeErrorKind> {
         match (self, rhs) {
-            (Atom::Float(lhs), Atom::Float(rhs)) => Atom::Float(lhs % rhs),
+            (Atom::Float(lhs), Atom::Float(rhs)) => Ok(Atom::Float(lhs % rhs)),
             // (Atom::Str(lhs), Atom::Str(rhs)) => Atom::Str(format!("{}{}", lhs, rhs)),
-            (_, _) => panic!("I can't mod those types"),
+            (_, _) => Err(RuntimeErrorKind::Type("I can't mod those types".to_string())),
         }
     }
 
-    fn fact(self) -> Self {
+    fn fact(self) -> Result<Self, RuntimeErrorKind> {
         match self {
-            Atom::Float(lhs) => Atom::Float(factorial(lhs) as Float),
+            Atom::Float(lhs) => Ok(Atom::Float(factorial(lhs) as Float)),
             // (Atom::Str(lhs), Atom::Str(rhs)) => Atom::Str(format!("{}{}", lhs, rhs)),
-            _ => panic!("I can't factorial those types"),
+            _ => Err(RuntimeErrorKind::Type("I can't factorial those types".to_string())),
         }
     }
 }
@@ -93,15 +210,24 @@ impl std::fmt::Display for Atom {
                         .collect::<Vec<String>>()
                         .join(" ")
                 }
-                Atom::BuiltIn(bi) => match bi {
-                    _ => "BuiltIn".to_string(),
-                },
+                Atom::Map(map) => format!(
+                    "{{{}}}",
+                    map.iter().map(|(k, v)| format!("{}: {}", k, v)).collect::<Vec<String>>().join(", ")
+                ),
+                Atom::BuiltIn(bi) => bi.def().symbol.to_string(),
             }
-            .to_string()
         )
     }
 }
 
+/// The entries of `atom`, or a type error saying what couldn't be done to it
+fn as_map(atom: Atom, doing: &str) -> Result<Vec<(Atom, Atom)>, RuntimeErrorKind> {
+    match atom {
+        Atom::Map(map) => Ok(map),
+        atom => Err(RuntimeErrorKind::Type(format!("I can't {} a {}, only a map", doing, atom.type_name()))),
+    }
+}
+
 /// Starting from the most basic, we define some built-in functions that our lisp has
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum BuiltIn {
@@ -120,6 +246,8 @@ pub enum BuiltIn {
     Range,
     Sum,
     Avg,
+    Floor,
+    Ceil,
 
     // Stack operators
     Len,
@@ -129,38 +257,62 @@ pub enum BuiltIn {
     Clear,
     Jmp,
 
+    // Array and string operators
+    Reverse,
+    Length,
+
     // Keywords
     Not,
     Print,
     PrintLn,
+    PrintRepr,
     Cmp,
     Dcmp,
     InChar,
+    FromJson,
+    ToJson,
+
+    // Map operators
+    Get,
+    Set,
+    Delete,
+    Keys,
+    Values,
+    HasKey,
+    Merge,
 }
 
 impl BuiltIn {
-    pub fn call(&self, stack: &mut Vec<Atom>, pc: &mut usize) {
+    /// Run this builtin. `pc` points at the next instruction, jumps change it.
+    pub fn call(
+        &self,
+        stack: &mut Vec<Atom>,
+        pc: &mut usize,
+        host: &mut dyn Host,
+        limits: &Limits,
+    ) -> Result<(), RuntimeErrorKind> {
         use BuiltIn::*;
+        let io_err = |e: std::io::Error| RuntimeErrorKind::Io(e.to_string());
         match self {
             // Operators
             Plus => {
                 if let (Some(b), Some(a)) = (stack.pop(), stack.pop()) {
-                    stack.push(a + b);
+                    stack.push((a + b)?);
                 }
             }
             Minus => {
                 if let (Some(b), Some(a)) = (stack.pop(), stack.pop()) {
-                    stack.push(a - b);
+                    stack.push((a - b)?);
                 }
             }
             Times => {
                 if let (Some(b), Some(a)) = (stack.pop(), stack.pop()) {
-                    stack.push(a * b);
+                    stack.push((a * b)?);
                 }
             }
             Divide => {
                 if let (Some(b), Some(a)) = (stack.pop(), stack.pop()) {
-                    stack.push(a / b);
+                    stack.push((a / b)?);
                 }
             }
             Equal => {
@@ -174,7 +326,7 @@ impl BuiltIn {
             }
             Power => {
                 if let (Some(b), Some(a)) = (stack.pop(), stack.pop()) {
-                    stack.push(a.pow(b));
+                    stack.push(a.pow(b)?);
                 }
             }
             Root => {
@@ -184,17 +336,17 @@ impl BuiltIn {
             }
             Modulus => {
                 if let (Some(b), Some(a)) = (stack.pop(), stack.pop()) {
-                    stack.push(a.modu(b));
+                    stack.push(a.modu(b)?);
                 }
             }
             Factorial => {
                 if let Some(a) = stack.pop() {
-                    stack.push(a.fact());
+                    stack.push(a.fact()?);
                 }
             }
             Negate => {
                 if let Some(Atom::Float(a)) = stack.pop() {
-                    stack.push(Atom::Float(-1.0 * a));
+                    stack.push(Atom::Float(-a));
                 }
             }
             Abs => {
@@ -215,9 +367,14 @@ impl BuiltIn {
                         _ => 0.0,
                     };
 
+                    let range = (a as usize)..((b + 1.0) as usize);
+                    // check before building it, a range can be huge
+                    if limits.too_big(range.len()) {
+                        return Err(RuntimeErrorKind::SizeLimit(limits.max_size.unwrap()));
+                    }
+
                     stack.push(Atom::Arr(
-                        ((a as usize)..((b + 1.0) as usize))
-                            .into_iter()
+                        range
                             .map(|x| Atom::Float(x as f64))
                             .collect::<Vec<Atom>>(),
                     ));
@@ -226,12 +383,10 @@ impl BuiltIn {
             Sum => {
                 if let Some(Atom::Float(a)) = stack.pop() {
                     let mut total = 0.0;
-                    for _ in 0..(a as usize) {
+                    // once the stack is empty the rest would only add zeroes
+                    for _ in 0..(a as usize).min(stack.len()) {
                         total += match stack.pop() {
-                            Some(atom) => match atom {
-                                Atom::Float(f) => f,
-                                _ => 0.0
-                            },
+                            Some(Atom::Float(f)) => f,
                             _ => 0.0
                         }
                     }
@@ -247,18 +402,25 @@ impl BuiltIn {
             Avg => { 
                 if let Some(Atom::Float(a)) = stack.pop() { 
                     let mut total = 0.0;
-                    for _ in 0..(a as usize) {
+                    for _ in 0..(a as usize).min(stack.len()) {
                         total += match stack.pop() {
-                            Some(atom) => match atom {
-                                Atom::Float(f) => f,
-                                _ => 0.0
-                            },
+                            Some(Atom::Float(f)) => f,
                             _ => 0.0
                         }
                     }
                     stack.push(Atom::Float(total / a));
                 }
             }
+            Floor => {
+                if let Some(Atom::Float(a)) = stack.pop() {
+                    stack.push(Atom::Float(a.floor()))
+                }
+            }
+            Ceil => {
+                if let Some(Atom::Float(a)) = stack.pop() {
+                    stack.push(Atom::Float(a.ceil()))
+                }
+            }
             Len => stack.push(Atom::Float(stack.len() as f64)),
             Swap => {
                 if let (Some(b), Some(a)) = (stack.pop(), stack.pop()) {
@@ -271,19 +433,38 @@ impl BuiltIn {
                     stack.push(a.clone())
                 }
             }
-            Drop => if let Some(_) = stack.pop() {},
+            Drop => {
+                stack.pop();
+            }
             Clear => {
                 *stack = Vec::new();
             }
             Jmp => {
                 if let Some(Atom::Float(a)) = stack.pop() {
                     *pc = {
-                        let target: usize = a.clone() as usize;
+                        let target: usize = a as usize;
                         target
                     };
                 }
             }
 
+            Reverse => match stack.pop() {
+                Some(Atom::Arr(mut arr)) => {
+                    arr.reverse();
+                    stack.push(Atom::Arr(arr))
+                }
+                Some(Atom::Str(st)) => stack.push(Atom::Str(st.chars().rev().collect())),
+                Some(atom) => stack.push(atom),
+                None => {}
+            },
+            Length => match stack.pop() {
+                Some(Atom::Arr(arr)) => stack.push(Atom::Float(arr.len() as f64)),
+                Some(Atom::Str(st)) => stack.push(Atom::Float(st.chars().count() as f64)),
+                Some(Atom::Map(map)) => stack.push(Atom::Float(map.len() as f64)),
+                Some(atom) => stack.push(atom),
+                None => {}
+            },
+
             Not => {
                 if let Some(Atom::Float(bo)) = stack.pop() {
                     stack.push(Atom::Float(match bo as usize {
@@ -295,12 +476,17 @@ impl BuiltIn {
             }
             PrintLn => {
                 if let Some(first_elem) = stack.pop() {
-                    println!("{}", first_elem)
+                    host.write(&format!("{}\n", first_elem)).map_err(io_err)?
                 }
             }
             Print => {
                 if let Some(first_elem) = stack.pop() {
-                    print!("{} ", first_elem)
+                    host.write(&format!("{} ", first_elem)).map_err(io_err)?
+                }
+            }
+            PrintRepr => {
+                if let Some(first_elem) = stack.pop() {
+                    host.write(&format!("{}\n", first_elem.repr())).map_err(io_err)?
                 }
             }
             Cmp => {
@@ -312,13 +498,82 @@ impl BuiltIn {
             }
             Dcmp => {
                 if let Some(Atom::Str(sym_str)) = stack.pop() {
-                    let atom = Atom::Str(
-                        String::from_utf8(decompress(sym_str.as_bytes()).unwrap()).unwrap(),
-                    );
+                    let atom = decompress(sym_str.as_bytes())
+                        .ok()
+                        .and_then(|bytes| String::from_utf8(bytes).ok())
+                        .map(Atom::Str)
+                        .ok_or(RuntimeErrorKind::BadCompressedString)?;
                     stack.push(atom);
                 }
             }
-            InChar => {}
+            FromJson => match stack.pop() {
+                Some(Atom::Str(st)) => {
+                    let value = serde_json::from_str(&st).map_err(|e| RuntimeErrorKind::BadJson(e.to_string()))?;
+                    stack.push(Atom::from_json(&value))
+                }
+                Some(atom) => stack.push(atom),
+                None => {}
+            },
+            ToJson => {
+                if let Some(atom) = stack.pop() {
+                    stack.push(Atom::Str(atom.to_json().to_string()))
+                }
+            }
+            InChar => {
+                let c = host.read_char().map_err(io_err)?;
+                stack.push(Atom::Str(c.map(String::from).unwrap_or_default()))
+            }
+
+            // Map operators
+            Get => {
+                if let (Some(key), Some(map)) = (stack.pop(), stack.pop()) {
+                    let map = as_map(map, "get from")?;
+                    stack.push(map_find(&map, &key).map_or(Atom::Float(0.0), |i| map[i].1.clone()));
+                }
+            }
+            Set => {
+                if let (Some(value), Some(key), Some(map)) = (stack.pop(), stack.pop(), stack.pop()) {
+                    let mut map = as_map(map, "set in")?;
+                    map_insert(&mut map, key, value);
+                    stack.push(Atom::Map(map));
+                }
+            }
+            Delete => {
+                if let (Some(key), Some(map)) = (stack.pop(), stack.pop()) {
+                    let mut map = as_map(map, "delete from")?;
+                    if let Some(i) = map_find(&map, &key) {
+                        map.remove(i);
+                    }
+                    stack.push(Atom::Map(map));
+                }
+            }
+            Keys => {
+                if let Some(map) = stack.pop() {
+                    stack.push(Atom::Arr(as_map(map, "list the keys of")?.into_iter().map(|(k, _)| k).collect()));
+                }
+            }
+            Values => {
+                if let Some(map) = stack.pop() {
+                    stack.push(Atom::Arr(as_map(map, "list the values of")?.into_iter().map(|(_, v)| v).collect()));
+                }
+            }
+            HasKey => {
+                if let (Some(key), Some(map)) = (stack.pop(), stack.pop()) {
+                    let found = map_find(&as_map(map, "look up in")?, &key).is_some();
+                    stack.push(Atom::Float(if found { 1.0 } else { 0.0 }));
+                }
+            }
+            Merge => {
+                if let (Some(b), Some(a)) = (stack.pop(), stack.pop()) {
+                    let mut map = as_map(a, "merge")?;
+                    for (k, v) in as_map(b, "merge")? {
+                        map_insert(&mut map, k, v);
+                    }
+                    stack.push(Atom::Map(map));
+                }
+            }
         }
+
+        Ok(())
     }
 }
@@ -0,0 +1,146 @@
+use super::*;
+
+/// Everything we know about a builtin, besides what it does
+#[derive(Debug, PartialEq)]
+pub struct BuiltInDef {
+    pub builtin: BuiltIn,
+    /// What you type to call it
+    pub symbol: &'static str,
+    pub name: &'static str,
+    /// Items popped off the stack
+    pub pops: usize,
+    /// Items pushed onto the stack
+    pub pushes: usize,
+    /// Also pops a number of items only known at runtime
+    pub variadic: bool,
+    /// Forth style stack effect, e.g. `a b -- a+b`
+    pub effect: &'static str,
+    pub description: &'static str,
+}
+
+macro_rules! def {
+    ($bi:ident, $sym:expr, $name:expr, $pops:expr => $pushes:expr, $effect:expr, $desc:expr) => {
+        def!($bi, $sym, $name, $pops => $pushes, false, $effect, $desc)
+    };
+    ($bi:ident, $sym:expr, $name:expr, $pops:expr => $pushes:expr, $variadic:expr, $effect:expr, $desc:expr) => {
+        BuiltInDef {
+            builtin: BuiltIn::$bi,
+            symbol: $sym,
+            name: $name,
+            pops: $pops,
+            pushes: $pushes,
+            variadic: $variadic,
+            effect: $effect,
+            description: $desc,
+        }
+    };
+}
+
+/// Every builtin the parser knows about
+pub const BUILTINS: &[BuiltInDef] = &[
+    // Operators
+    def!(Plus, "+", "add", 2 => 1, "a b -- a+b", "adds `a` and `b`, or joins two strings"),
+    def!(Minus, "-", "sub", 2 => 1, "a b -- a-b", "subtracts `b` from `a`"),
+    def!(Times, "*", "multi", 2 => 1, "a b -- a*b", "multiplies `a` by `b`"),
+    def!(Divide, "/", "div", 2 => 1, "a b -- a/b", "divides `a` by `b`"),
+    def!(Equal, "=", "equal", 2 => 1, "a b -- a==b", "pushes `1` if `a` equals `b`, otherwise `0`"),
+    def!(Power, "^", "power", 2 => 1, "a b -- a^b", "raises `a` to the power of `b`"),
+    def!(Root, "R", "root", 1 => 1, "a -- sqrt(a)", "square root of `a`"),
+    def!(Modulus, "%", "mod", 2 => 1, "a b -- a%b", "remainder of `a` divided by `b`"),
+    def!(Factorial, "!", "factorial", 1 => 1, "a -- a!", "factorial of `a`"),
+    def!(Negate, "N", "negate", 1 => 1, "a -- -a", "negates `a`"),
+    def!(Abs, "A", "absolute", 1 => 1, "a -- abs(a)", "absolute value of `a`"),
+    def!(Range, ":", "range", 2 => 1, "a b -- [a..b]", "inclusive range from `a` to `b`"),
+    def!(Sum, "S", "sum", 1 => 1, true, "x.. n -- sum", "pops `n` then sums `n` values from the stack"),
+    def!(Avg, "G", "avg", 1 => 1, true, "x.. n -- avg", "pops `n` then averages `n` values from the stack"),
+    def!(Floor, ".f", "floor", 1 => 1, "a -- floor(a)", "rounds `a` down"),
+    def!(Ceil, ".c", "ceil", 1 => 1, "a -- ceil(a)", "rounds `a` up"),
+    // Stack operators
+    def!(Len, "~", "len", 0 => 1, "-- len", "pushes the current length of the stack"),
+    def!(Swap, "s", "swap", 2 => 2, "a b -- b a", "swaps the top two values"),
+    def!(Dupe, "d", "dupe", 1 => 2, "a -- a a", "duplicates the top value"),
+    def!(Drop, "x", "drop", 1 => 0, "a --", "drops the top value"),
+    def!(Clear, "c", "clear", 0 => 0, true, ".. --", "clears the stack"),
+    def!(Jmp, "J", "jump", 1 => 0, "a --", "jumps to instruction `a`, counted from the start of the file or REPL line"),
+    // Array and string operators
+    def!(Reverse, ".r", "reverse", 1 => 1, "a -- rev(a)", "reverses an array or string"),
+    def!(Length, ".l", "length", 1 => 1, "a -- len(a)", "length of an array or string"),
+    // Keywords
+    def!(Not, "n", "not", 1 => 1, "a -- !a", "pushes `1` if `a` is `0`, otherwise `0`"),
+    def!(Print, "P", "print", 1 => 0, "a --", "prints `a` to `stdout` (w/o newline)"),
+    def!(PrintLn, ",", "println", 1 => 0, "a --", "prints `a` to `stdout` (w/ newline)"),
+    def!(PrintRepr, ".p", "printrepr", 1 => 0, "a --", "prints `a` with its type visible, quoting strings and bracketing arrays (w/ newline)"),
+    def!(Cmp, "cmp", "compress", 1 => 1, "s -- cmp(s)", "compresses the string `s`"),
+    def!(Dcmp, "dmp", "decompress", 1 => 1, "s -- dmp(s)", "decompresses the string `s`"),
+    def!(InChar, ".i", "inchar", 0 => 1, "-- c", "reads one character of input, or an empty string at the end"),
+    def!(FromJson, ".j", "fromjson", 1 => 1, "s -- a", "parses the JSON document `s`, objects become maps"),
+    def!(ToJson, ".J", "tojson", 1 => 1, "a -- s", "writes `a` as JSON"),
+    // Map operators
+    def!(Get, ".g", "get", 2 => 1, "m k -- v", "value of key `k` in map `m`, or `0` if it isn't there"),
+    def!(Set, ".s", "set", 3 => 1, "m k v -- m", "sets key `k` of map `m` to `v`"),
+    def!(Delete, ".d", "delete", 2 => 1, "m k -- m", "removes key `k` from map `m`"),
+    def!(Keys, ".k", "keys", 1 => 1, "m -- [k..]", "the keys of map `m`, in order"),
+    def!(Values, ".v", "values", 1 => 1, "m -- [v..]", "the values of map `m`, in the order of their keys"),
+    def!(HasKey, ".h", "haskey", 2 => 1, "m k -- b", "pushes `1` if map `m` has key `k`, otherwise `0`"),
+    def!(Merge, ".m", "merge", 2 => 1, "m1 m2 -- m", "merges two maps, keys of `m2` win"),
+];
+
+/// Find the builtin spelled exactly `symbol`
+pub fn lookup(symbol: &str) -> Option<&'static BuiltInDef> {
+    BUILTINS.iter().find(|def| def.symbol == symbol)
+}
+
+/// Find the builtin with the longest symbol `i` starts with
+pub fn longest_match(i: &str) -> Option<&'static BuiltInDef> {
+    BUILTINS
+        .iter()
+        .filter(|def| i.starts_with(def.symbol))
+        .max_by_key(|def| def.symbol.len())
+}
+
+/// Markdown reference table of all builtins, as found in the README
+pub fn markdown_table() -> String {
+    let mut table = String::from("|Symbol|Name|Stack effect|Description|\n|------|----|------------|-----------|\n");
+    for def in BUILTINS {
+        table.push_str(&format!(
+            "|`{}`|{}|`{}`|{}|\n",
+            def.symbol.replace("|", "\\|"),
+            def.name,
+            def.effect,
+            def.description
+        ));
+    }
+    table
+}
+
+impl std::fmt::Display for BuiltInDef {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:<4} {:<11} {:<14} {}", self.symbol, self.name, self.effect, self.description)
+    }
+}
+
+impl BuiltIn {
+    /// Registry entry for this builtin
+    pub fn def(&self) -> &'static BuiltInDef {
+        BUILTINS
+            .iter()
+            .find(|def| def.builtin == *self)
+            .expect("builtin missing from the registry")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::{tokenize, TokenKind};
+
+    #[test]
+    fn assert_symbols_lex_to_builtins() {
+        for def in BUILTINS {
+            let kinds: Vec<TokenKind> = tokenize(def.symbol).unwrap().into_iter().map(|t| t.kind).collect();
+            assert_eq!(kinds, vec![TokenKind::BuiltIn(def.builtin)], "{}", def.symbol);
+            assert_eq!(def.builtin.def(), def);
+            assert_eq!(lookup(def.symbol), Some(def));
+        }
+    }
+}
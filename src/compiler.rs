@@ -0,0 +1,193 @@
+use super::*;
+
+/// One bytecode instruction, there is one for every instruction it was compiled from
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Op {
+    /// Push constant `n` of the pool
+    Push(usize),
+    /// A literal right before a `J`: instead of pushing constant `value` and having the
+    /// `J` pop it again, go straight to `target`. The `J` itself is still there for
+    /// jumps that land on it.
+    Jump { value: usize, target: usize },
+    Call(BuiltIn),
+}
+
+/// Compiled instructions, ready to run
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Program {
+    pub ops: Vec<Op>,
+    /// Literals pushed by `Push` and `Jump`
+    pub consts: Vec<Atom>,
+    /// Source span of each op
+    pub spans: Vec<Span>,
+}
+
+impl Program {
+    /// Index of `atom` in the constant pool, adding it if it isn't there yet
+    fn constant(&mut self, atom: &Atom) -> usize {
+        match self.consts.iter().position(|c| c == atom) {
+            Some(n) => n,
+            None => {
+                self.consts.push(atom.clone());
+                self.consts.len() - 1
+            }
+        }
+    }
+
+    /// Append `chunk` after the ops already here, moving its jumps and constants along
+    pub fn extend(&mut self, chunk: &Program) {
+        let base = self.ops.len();
+        for op in &chunk.ops {
+            let op = match op {
+                Op::Push(n) => Op::Push(self.constant(&chunk.consts[*n])),
+                Op::Jump { value, target } => Op::Jump {
+                    value: self.constant(&chunk.consts[*value]),
+                    target: base + target,
+                },
+                Op::Call(bi) => Op::Call(*bi),
+            };
+            self.ops.push(op);
+        }
+        self.spans.extend(chunk.spans.iter().cloned());
+    }
+
+    /// The instruction op `i` was compiled from
+    pub fn instruction(&self, i: usize) -> Atom {
+        match self.ops[i] {
+            Op::Push(n) | Op::Jump { value: n, .. } => self.consts[n].clone(),
+            Op::Call(bi) => Atom::BuiltIn(bi),
+        }
+    }
+
+    /// Every instruction this was compiled from
+    pub fn instructions(&self) -> Vec<Atom> {
+        (0..self.ops.len()).map(|i| self.instruction(i)).collect()
+    }
+}
+
+/// Lower `atoms` into a program of its own, with jumps counting from its start
+pub fn compile(atoms: &[Atom], spans: &[Span]) -> Program {
+    let mut program = Program::default();
+
+    for (i, atom) in atoms.iter().enumerate() {
+        let op = match (atom, atoms.get(i + 1)) {
+            (Atom::BuiltIn(bi), _) => Op::Call(*bi),
+            // a jump past the end stays as it is, so it fails when it runs like it always did
+            (Atom::Float(f), Some(Atom::BuiltIn(BuiltIn::Jmp))) if *f as usize <= atoms.len() => Op::Jump {
+                value: program.constant(atom),
+                target: *f as usize,
+            },
+            _ => Op::Push(program.constant(atom)),
+        };
+        program.ops.push(op);
+    }
+    program.spans.extend(spans.iter().cloned());
+
+    program
+}
+
+/// One line per op of `program`: its index, opcode, operands and where in `src` it came
+/// from. Ops that a resolved jump lands on are marked with `>`.
+pub fn disassemble(program: &Program, src: &str) -> String {
+    let targets: Vec<usize> = program
+        .ops
+        .iter()
+        .filter_map(|op| match op {
+            Op::Jump { target, .. } => Some(*target),
+            _ => None,
+        })
+        .collect();
+
+    let mut out = String::new();
+    for i in 0..program.ops.len() {
+        out.push_str(if targets.contains(&i) { ">" } else { " " });
+        out.push_str(&disassemble_op(program, i, src));
+        out.push('\n');
+    }
+
+    out
+}
+
+/// Op `i` of `program` the way `disassemble` shows it, without the jump marker
+pub fn disassemble_op(program: &Program, i: usize, src: &str) -> String {
+    let literal = |n: usize| program.consts[n].repr();
+
+    let (opcode, operands) = match program.ops[i] {
+        Op::Push(n) => ("push", literal(n)),
+        Op::Jump { value, target } => ("jump", format!("{} -> {}", literal(value), target)),
+        Op::Call(bi) if bi == BuiltIn::Jmp => ("call", format!("{} -> ?", bi.def().symbol)),
+        Op::Call(bi) => ("call", format!("{} ({})", bi.def().symbol, bi.def().name)),
+    };
+    let span = program.spans[i];
+    let (line, col) = span.line_col(src);
+
+    format!(
+        "{:>4}  {:<5} {:<20} {:<7} {}",
+        i,
+        opcode,
+        operands,
+        format!("{}:{}", line, col),
+        src.get(span.start..span.end).unwrap_or("").replace('\n', "\\n"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile_src(src: &str) -> Program {
+        match parser::parse_program(src).unwrap() {
+            (Expr::Function(atoms), spans) => compile(&atoms, &spans),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn assert_jumps_resolve() {
+        let program = compile_src("1 3J 1 1+");
+        assert_eq!(program.consts, vec![Atom::Float(1.0), Atom::Float(3.0)]);
+        assert_eq!(
+            program.ops,
+            vec![
+                Op::Push(0),
+                Op::Jump { value: 1, target: 3 },
+                Op::Call(BuiltIn::Jmp),
+                Op::Push(0),
+                Op::Push(0),
+                Op::Call(BuiltIn::Plus),
+            ]
+        );
+        assert_eq!(compile_src("9J").ops[0], Op::Push(0));
+    }
+
+    #[test]
+    fn assert_disassemble() {
+        let src = "1 3J\n\"a\"P";
+        let lines: Vec<String> = disassemble(&compile_src(src), src)
+            .lines()
+            .map(|l| l.split_whitespace().collect::<Vec<_>>().join(" "))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "0 push 1 1:1 1",
+                "1 jump 3 -> 3 1:3 3",
+                "2 call J -> ? 1:4 J",
+                "> 3 push \"a\" 2:1 \"a\"",
+                "4 call P (print) 2:4 P",
+            ]
+        );
+    }
+
+    #[test]
+    fn assert_extend_moves_chunks() {
+        let mut program = compile_src("\"a\" 1");
+        program.extend(&compile_src("1 0J"));
+        assert_eq!(program.ops[3], Op::Jump { value: 2, target: 2 });
+        assert_eq!(program.consts.len(), 3);
+        assert_eq!(program.instructions(), parser::parse_expr("\"a\" 1 1 0J").map(|e| match e {
+            Expr::Function(atoms) => atoms,
+            _ => unreachable!(),
+        }).unwrap());
+    }
+}
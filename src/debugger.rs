@@ -0,0 +1,472 @@
+use putt::atom::{Atom, BuiltIn};
+use putt::compiler::{disassemble_op, Op};
+use putt::host::{Host, StdHost};
+use putt::*;
+use rustyline::{error::ReadlineError, Editor};
+use std::{collections::BTreeSet, error::Error};
+
+const PROMPT: &str = "(debug) ";
+
+const COMMANDS: &str = "\
+s, step [n]         run the next n instructions (default 1)
+c, continue         run until a breakpoint or the end
+b, break <at>       stop before instruction <at>, or at source position <line:col>
+d, delete <at>      remove the breakpoint at <at>
+p, print [i]        show the whole stack, or only entry i (0 is the bottom)
+l, list             show every instruction, marking breakpoints and the next one
+r, rewind [n]       undo the last n instructions (default 1)
+rc, reverse         rewind to the last breakpoint passed, or the start
+g, goto <step>      go back or forward to just after step <step> (0 is the start)
+q, quit             stop debugging
+h, help             show this";
+
+#[derive(Debug, PartialEq)]
+enum Command<'a> {
+    Step(usize),
+    Continue,
+    Break(&'a str),
+    Delete(&'a str),
+    Print(Option<usize>),
+    List,
+    Rewind(usize),
+    Reverse,
+    Goto(usize),
+    Quit,
+    Help,
+}
+
+fn parse_command(line: &str) -> Result<Command<'_>, String> {
+    let line = line.trim();
+    let (cmd, arg) = line.split_at(line.find(char::is_whitespace).unwrap_or(line.len()));
+    let arg = arg.trim();
+    let number = |default: Option<usize>| match arg {
+        "" => default.ok_or_else(|| format!("{} needs an argument", cmd)),
+        _ => arg.parse::<usize>().map_err(|e| format!("bad number {:?}: {}", arg, e)),
+    };
+
+    match cmd {
+        // an empty line steps, like most debuggers
+        "" | "s" | "step" => number(Some(1)).map(Command::Step),
+        "c" | "continue" => Ok(Command::Continue),
+        "b" | "break" if !arg.is_empty() => Ok(Command::Break(arg)),
+        "d" | "delete" if !arg.is_empty() => Ok(Command::Delete(arg)),
+        "b" | "break" | "d" | "delete" => Err(format!("{} needs an instruction or line:col", cmd)),
+        "p" | "print" if arg.is_empty() => Ok(Command::Print(None)),
+        "p" | "print" => number(None).map(|i| Command::Print(Some(i))),
+        "l" | "list" => Ok(Command::List),
+        "r" | "rewind" => number(Some(1)).map(Command::Rewind),
+        "rc" | "reverse" => Ok(Command::Reverse),
+        "g" | "goto" => number(None).map(Command::Goto),
+        "q" | "quit" => Ok(Command::Quit),
+        "h" | "help" => Ok(Command::Help),
+        _ => Err(format!("unknown command {:?}", cmd)),
+    }
+}
+
+/// Byte offset of `line:col` in `src`, both counting from 1
+fn offset(src: &str, line: usize, col: usize) -> Option<usize> {
+    let start = if line == 1 {
+        0
+    } else {
+        src.match_indices('\n').nth(line.checked_sub(2)?)?.0 + 1
+    };
+    let text = src[start..].split('\n').next()?;
+    text.char_indices().map(|(i, _)| i).chain(Some(text.len())).nth(col.checked_sub(1)?).map(|i| start + i)
+}
+
+/// What one instruction did, enough to undo it
+struct Change {
+    /// Where the instruction was
+    pc: usize,
+    printed: bool,
+    /// Values it took off the stack, bottom first
+    removed: Vec<Atom>,
+    /// How many values it left on top instead
+    added: usize,
+    /// The character it read from input, if any
+    read: Option<char>,
+}
+
+impl Change {
+    fn undo<H: Host>(self, putt: &mut Putt<H>) {
+        let kept = putt.stack.len() - self.added;
+        putt.stack.truncate(kept);
+        putt.stack.extend(self.removed);
+        putt.pc = self.pc;
+        putt.printed = self.printed;
+        putt.executed -= 1;
+        if let Some(c) = self.read {
+            putt.host.unread(c);
+        }
+    }
+}
+
+struct Debugger<'a, H: Host = StdHost> {
+    putt: Putt<H>,
+    src: &'a str,
+    breakpoints: BTreeSet<usize>,
+    /// Every instruction run so far, oldest first
+    history: Vec<Change>,
+}
+
+impl<'a, H: Host> Debugger<'a, H> {
+    fn new(putt: Putt<H>, src: &'a str) -> Self {
+        Debugger { putt, src, breakpoints: BTreeSet::new(), history: Vec::new() }
+    }
+
+    /// Which instruction `at` means: an index, or the first instruction at or after `line:col`
+    fn resolve(&self, at: &str) -> Result<usize, String> {
+        let len = self.putt.code.ops.len();
+
+        let i = match at.split_once(':') {
+            Some((line, col)) => {
+                let parse = |n: &str| n.parse::<usize>().map_err(|e| format!("bad position {:?}: {}", at, e));
+                let pos = offset(self.src, parse(line)?, parse(col)?)
+                    .ok_or_else(|| format!("{} is outside the program", at))?;
+                self.putt.code.spans.iter().position(|span| span.end > pos).unwrap_or(len)
+            }
+            None => at.parse::<usize>().map_err(|e| format!("bad instruction {:?}: {}", at, e))?,
+        };
+
+        if i < len {
+            Ok(i)
+        } else {
+            Err(format!("there is no instruction at {}", at))
+        }
+    }
+
+    /// Run up to `steps` instructions, or until the end if `None`, stopping early
+    /// at breakpoints other than the one we start on. An instruction that fails is
+    /// undone, so it can be looked at and rewound from.
+    fn run(&mut self, steps: Option<usize>) -> Result<(), RuntimeError> {
+        let mut ran = 0;
+        while !self.putt.done() && steps.is_none_or(|n| ran < n) {
+            if ran > 0 && self.breakpoints.contains(&self.putt.pc) {
+                println!("Breakpoint at {}", self.putt.pc);
+                break;
+            }
+            let (pc, printed, executed) = (self.putt.pc, self.putt.printed, self.putt.executed);
+            // only what the instruction can take off the stack needs keeping
+            let op = self.putt.code.ops[pc];
+            let len = self.putt.stack.len();
+            let kept = match op {
+                Op::Call(bi) if bi.def().variadic => 0,
+                Op::Call(bi) => len - bi.def().pops.min(len),
+                _ => len,
+            };
+            let removed = self.putt.stack[kept..].to_vec();
+
+            if let Err(e) = self.putt.step() {
+                self.putt.stack.truncate(kept);
+                self.putt.stack.extend(removed);
+                self.putt.pc = pc;
+                self.putt.printed = printed;
+                self.putt.executed = executed;
+                return Err(e);
+            }
+
+            let read = match (op, self.putt.stack.last()) {
+                (Op::Call(BuiltIn::InChar), Some(Atom::Str(c))) => c.chars().next(),
+                _ => None,
+            };
+            let added = self.putt.stack.len() - kept;
+            self.history.push(Change { pc, printed, removed, added, read });
+            ran += 1;
+        }
+        Ok(())
+    }
+
+    /// Undo up to `steps` instructions, or until the start if `None`, stopping early
+    /// at breakpoints other than the one we start on. Input that was read is given back,
+    /// but output can't be taken back.
+    fn rewind(&mut self, steps: Option<usize>) {
+        let mut undone = 0;
+        while steps.is_none_or(|n| undone < n) {
+            let change = match self.history.pop() {
+                Some(change) => change,
+                None => break,
+            };
+            change.undo(&mut self.putt);
+            undone += 1;
+
+            if steps.is_none() && self.breakpoints.contains(&self.putt.pc) {
+                println!("Breakpoint at {}", self.putt.pc);
+                break;
+            }
+        }
+    }
+
+    /// Go to just after step `step`, rewinding or running as needed
+    fn goto(&mut self, step: usize) -> Result<(), RuntimeError> {
+        let now = self.history.len();
+        if step <= now {
+            self.rewind(Some(now - step));
+        } else {
+            let before = self.breakpoints.clone();
+            // breakpoints on the way shouldn't get in the way of going somewhere exact
+            self.breakpoints.clear();
+            let result = self.run(Some(step - now));
+            self.breakpoints = before;
+            result?;
+        }
+        Ok(())
+    }
+
+    fn show_stack(&self) {
+        let stack: Vec<String> = self.putt.stack.iter().map(Atom::repr).collect();
+        println!("stack: [{}]", stack.join(", "));
+    }
+
+    /// Show how far along we are, the next instruction and the stack
+    fn show(&self) {
+        if self.putt.done() {
+            println!("[{}] Program finished", self.history.len());
+        } else {
+            println!("[{}] {}", self.history.len(), disassemble_op(&self.putt.code, self.putt.pc, self.src));
+        }
+        self.show_stack();
+    }
+
+    fn list(&self) {
+        for i in 0..self.putt.code.ops.len() {
+            let mark = match (i == self.putt.pc, self.breakpoints.contains(&i)) {
+                (true, _) => "=>",
+                (false, true) => " *",
+                _ => "  ",
+            };
+            println!("{}{}", mark, disassemble_op(&self.putt.code, i, self.src));
+        }
+    }
+
+    /// Carry out `cmd`, returning whether to keep debugging
+    fn command(&mut self, cmd: Command) -> bool {
+        let result = match cmd {
+            Command::Step(n) => self.run(Some(n)),
+            Command::Continue => self.run(None),
+            Command::Rewind(n) => {
+                self.rewind(Some(n));
+                Ok(())
+            }
+            Command::Reverse => {
+                self.rewind(None);
+                Ok(())
+            }
+            Command::Goto(step) => self.goto(step),
+            Command::Break(at) => {
+                match self.resolve(at) {
+                    Ok(i) => {
+                        self.breakpoints.insert(i);
+                        println!("Breakpoint set at {}", i);
+                    }
+                    Err(e) => eprintln!("Error: {}", e),
+                }
+                return true;
+            }
+            Command::Delete(at) => {
+                match self.resolve(at) {
+                    Ok(i) if self.breakpoints.remove(&i) => println!("Breakpoint at {} deleted", i),
+                    Ok(i) => eprintln!("Error: no breakpoint at {}", i),
+                    Err(e) => eprintln!("Error: {}", e),
+                }
+                return true;
+            }
+            Command::Print(None) => {
+                self.show_stack();
+                return true;
+            }
+            Command::Print(Some(i)) => {
+                match self.putt.stack.get(i) {
+                    Some(atom) => println!("{:>4}  {:<7} {}", i, atom.type_name(), atom.repr()),
+                    None => eprintln!("Error: the stack only has {} entries", self.putt.stack.len()),
+                }
+                return true;
+            }
+            Command::List => {
+                self.list();
+                return true;
+            }
+            Command::Help => {
+                println!("{}", COMMANDS);
+                return true;
+            }
+            Command::Quit => return false,
+        };
+
+        match result {
+            Ok(()) => {
+                self.show();
+                true
+            }
+            // stay open, rewinding from a failure is what stepping back is for
+            Err(e) => {
+                crate::report(self.src, &e);
+                self.show();
+                true
+            }
+        }
+    }
+}
+
+/// Step through `src` one instruction at a time, taking commands from the terminal
+pub fn run(mut putt: Putt, src: &str) -> Result<(), Box<dyn Error>> {
+    // folding would leave instructions that aren't in the source
+    putt.optimize = false;
+    putt.parse(src)?;
+    if let Some(program) = putt.compile() {
+        putt.load_program(&program);
+    }
+
+    let mut debugger = Debugger::new(putt, src);
+    let mut rl = Editor::<()>::new();
+    println!("Type h for help");
+    debugger.show();
+
+    loop {
+        let line = match rl.readline(PROMPT) {
+            Ok(line) => line,
+            Err(ReadlineError::Eof) => break,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        rl.add_history_entry(line.as_str());
+
+        let keep_going = match parse_command(&line) {
+            Ok(cmd) => debugger.command(cmd),
+            Err(e) => {
+                eprintln!("Error: {}\n{}", e, COMMANDS);
+                true
+            }
+        };
+        if !keep_going {
+            break;
+        }
+    }
+
+    if debugger.putt.done() {
+        debugger.putt.finish()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use putt::host::BufferHost;
+
+    fn debugger(src: &str) -> Debugger<'_, BufferHost> {
+        with_input(src, "")
+    }
+
+    fn with_input<'a>(src: &'a str, input: &str) -> Debugger<'a, BufferHost> {
+        let mut putt = Putt::with_host(BufferHost::new(input));
+        putt.optimize = false;
+        putt.parse(src).unwrap();
+        let program = putt.compile().unwrap();
+        putt.load_program(&program);
+        Debugger::new(putt, src)
+    }
+
+    #[test]
+    fn assert_parse_commands() {
+        assert_eq!(parse_command(""), Ok(Command::Step(1)));
+        assert_eq!(parse_command("s 3"), Ok(Command::Step(3)));
+        assert_eq!(parse_command("break 2:4"), Ok(Command::Break("2:4")));
+        assert_eq!(parse_command("p"), Ok(Command::Print(None)));
+        assert_eq!(parse_command("p 1"), Ok(Command::Print(Some(1))));
+        assert!(parse_command("b").is_err());
+        assert!(parse_command("s x").is_err());
+        assert_eq!(parse_command("r"), Ok(Command::Rewind(1)));
+        assert_eq!(parse_command("g 12"), Ok(Command::Goto(12)));
+        assert!(parse_command("g").is_err());
+    }
+
+    #[test]
+    fn assert_breakpoints() {
+        let mut dbg = debugger("1 2+\n3 4+\nd");
+        assert_eq!(dbg.resolve("2:1"), Ok(3));
+        assert_eq!(dbg.resolve("1:4"), Ok(2));
+        assert_eq!(dbg.resolve("1:5"), Ok(3));
+        assert_eq!(dbg.resolve("6"), Ok(6));
+        assert!(dbg.resolve("7").is_err());
+        assert!(dbg.resolve("4:1").is_err());
+
+        dbg.breakpoints.insert(3);
+        dbg.breakpoints.insert(7);
+        dbg.run(None).unwrap();
+        assert_eq!(dbg.putt.pc, 3);
+        assert_eq!(dbg.putt.stack, vec![Atom::Float(3.0)]);
+
+        dbg.run(Some(2)).unwrap();
+        assert_eq!(dbg.putt.pc, 5);
+        dbg.run(None).unwrap();
+        assert_eq!(dbg.putt.pc, 7);
+        dbg.run(None).unwrap();
+        assert!(dbg.putt.done());
+    }
+
+    #[test]
+    fn assert_failures_are_undone() {
+        let mut dbg = debugger("1 2+ \"a\"*");
+        assert!(dbg.run(None).is_err());
+        assert_eq!(dbg.putt.pc, 4);
+        assert!(!dbg.putt.done());
+        assert_eq!(dbg.putt.stack, vec![Atom::Float(3.0), Atom::Str("a".to_string())]);
+        assert_eq!(dbg.history.len(), 4);
+        assert!(dbg.command(Command::Rewind(1)));
+        assert_eq!(dbg.putt.pc, 3);
+        assert!(dbg.command(Command::Continue));
+        assert_eq!(dbg.putt.pc, 4);
+    }
+
+    #[test]
+    fn assert_time_travel() {
+        // counts down from 3, leaving each number on the stack
+        let mut dbg = debugger("3 d1-d0=XI*1+J");
+        dbg.run(Some(9)).unwrap();
+        let (stack, pc) = (dbg.putt.stack.clone(), dbg.putt.pc);
+
+        dbg.run(None).unwrap();
+        assert!(dbg.putt.done());
+        let end = dbg.history.len();
+
+        dbg.goto(9).unwrap();
+        assert_eq!((&dbg.putt.stack, dbg.putt.pc), (&stack, pc));
+
+        dbg.rewind(Some(9));
+        assert_eq!(dbg.putt.stack, vec![]);
+        assert_eq!(dbg.putt.pc, 0);
+        assert_eq!(dbg.putt.executed, 0);
+
+        dbg.breakpoints.insert(7);
+        dbg.goto(end).unwrap();
+        assert!(dbg.putt.done());
+        dbg.rewind(None);
+        assert_eq!(dbg.putt.pc, 7);
+        assert_eq!(dbg.putt.stack, vec![3.0, 2.0, 1.0, 0.0, 1.0].into_iter().map(Atom::Float).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn assert_only_touched_values_are_kept() {
+        let mut dbg = debugger("1 2 3+ 2S");
+        dbg.run(None).unwrap();
+        let removed: Vec<_> = dbg.history.iter().map(|change| change.removed.len()).collect();
+        assert_eq!(removed, vec![0, 0, 0, 2, 0, 3]);
+        dbg.rewind(None);
+        assert_eq!(dbg.putt.stack, vec![]);
+    }
+
+    #[test]
+    fn assert_rewind_gives_input_back() {
+        let mut dbg = with_input(".i.i+.i", "ab");
+        dbg.run(None).unwrap();
+        assert_eq!(dbg.putt.stack, vec![Atom::Str("ab".to_string()), Atom::Str(String::new())]);
+
+        dbg.rewind(Some(3));
+        assert_eq!(dbg.putt.host.input, "b".chars().collect::<Vec<_>>());
+        dbg.rewind(None);
+        assert_eq!(dbg.putt.host.input, "ab".chars().collect::<Vec<_>>());
+        dbg.run(None).unwrap();
+        assert_eq!(dbg.putt.stack[0], Atom::Str("ab".to_string()));
+    }
+}
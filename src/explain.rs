@@ -0,0 +1,95 @@
+use super::*;
+use lexer::ParseError;
+
+/// How one instruction changes the depth of the stack, given what we know about the stack
+fn step_depth(atom: &Atom, depth: Option<usize>, known_top: Option<Float>) -> (Option<usize>, bool) {
+    let def = match atom {
+        Atom::BuiltIn(bi) => bi.def(),
+        _ => return (depth.map(|d| d + 1), false),
+    };
+
+    let depth = match depth {
+        Some(d) => d,
+        None => return (None, false),
+    };
+
+    let pops = match (def.builtin, def.variadic) {
+        (BuiltIn::Clear, _) => depth,
+        (_, true) => match known_top {
+            Some(n) => def.pops + n as usize,
+            None => return (None, false),
+        },
+        _ => def.pops,
+    };
+
+    if depth < pops {
+        // builtins pop what they can, then give up without pushing
+        (Some(0), true)
+    } else {
+        (Some(depth - pops + def.pushes), false)
+    }
+}
+
+/// One line per instruction of `src`: its source, what it does and the stack depth after it
+pub fn explain(src: &str) -> Result<String, ParseError> {
+    let (exp, spans) = parser::parse_program(src)?;
+    let atoms = match exp {
+        Expr::Function(atoms) => atoms,
+        _ => unreachable!(),
+    };
+
+    let texts: Vec<String> = spans.iter().map(|s| src[s.start..s.end].replace('\n', "\\n")).collect();
+    let width = texts.iter().map(|t| t.chars().count()).max().unwrap_or(0);
+
+    let mut out = String::new();
+    let mut depth = Some(0);
+    let mut known_top = None;
+
+    for (atom, text) in atoms.iter().zip(texts) {
+        let (name, effect) = match atom {
+            Atom::BuiltIn(bi) => (bi.def().name.to_string(), bi.def().effect.to_string()),
+            Atom::Str(_) => ("push".to_string(), "-- str".to_string()),
+            _ => ("push".to_string(), format!("-- {}", atom)),
+        };
+
+        let (next, underflow) = step_depth(atom, depth, known_top);
+        depth = next;
+        known_top = match atom {
+            Atom::Float(f) => Some(*f),
+            _ => None,
+        };
+
+        out.push_str(&format!(
+            "{:<width$}  {:<11} {:<14} {}{}\n",
+            text,
+            name,
+            effect,
+            depth.map_or("?".to_string(), |d| d.to_string()),
+            if underflow { " (underflow)" } else { "" },
+            width = width
+        ));
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn depths(src: &str) -> Vec<String> {
+        explain(src)
+            .unwrap()
+            .lines()
+            .map(|l| l.split_whitespace().last().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn assert_depths() {
+        assert_eq!(depths("X 1+d"), vec!["1", "2", "1", "2"]);
+        assert_eq!(depths("1 2 3 3S c"), vec!["1", "2", "3", "4", "1", "0"]);
+        assert_eq!(depths("~S 1"), vec!["1", "?", "?"]);
+        assert!(explain("1+").unwrap().ends_with("0 (underflow)\n"));
+    }
+}
@@ -0,0 +1,90 @@
+use super::*;
+use lexer::{tokenize, ParseError, Token, TokenKind};
+
+/// Whether `next` has to be separated from `prev` to still lex as two tokens,
+/// e.g. `C M` (not `CM`), `1 2` (not `12`) or `- 1` (not `-1`)
+fn needs_space(prev: &str, next: &str, prev_kind: &TokenKind, next_kind: &TokenKind) -> bool {
+    match tokenize(&format!("{}{}", prev, next)) {
+        Ok(tokens) => {
+            let kinds: Vec<&TokenKind> = tokens.iter().map(|t| &t.kind).collect();
+            kinds != vec![prev_kind, next_kind]
+        }
+        Err(_) => true,
+    }
+}
+
+fn token_text<'a>(src: &'a str, token: &Token) -> &'a str {
+    &src[token.span.start..token.span.end]
+}
+
+/// `src` with every whitespace that can go, removed
+pub fn minify(src: &str) -> Result<String, Box<dyn Error>> {
+    let tokens = tokenize(src)?;
+    let mut out = String::new();
+    let mut prev: Option<&Token> = None;
+
+    for token in &tokens {
+        let text = token_text(src, token);
+        if let Some(p) = prev {
+            if needs_space(token_text(src, p), text, &p.kind, &token.kind) {
+                out.push(' ');
+            }
+        }
+        out.push_str(text);
+        prev = Some(token);
+    }
+
+    if parser::parse_expr(&out)? != parser::parse_expr(src)? {
+        return Err(format!("minified program {:?} does not parse back to the same instructions", out).into());
+    }
+
+    Ok(out)
+}
+
+/// `src` with one token per line, each annotated with what it does. The notes are for
+/// reading, the language has no comments, so the result doesn't parse back.
+pub fn expand(src: &str) -> Result<String, ParseError> {
+    let tokens = tokenize(src)?;
+    let width = tokens.iter().map(|t| token_text(src, t).chars().count()).max().unwrap_or(0);
+    let mut out = String::new();
+
+    for token in &tokens {
+        let note = match &token.kind {
+            TokenKind::BuiltIn(bi) => format!("{} ({})", bi.def().name, bi.def().effect),
+            TokenKind::Float(f) => format!("push {}", f),
+            TokenKind::Roman(num) => format!("push {}", num),
+            TokenKind::Bool(b) => format!("push {}", if *b { 1 } else { 0 }),
+            TokenKind::Str(_) => "push string".to_string(),
+            TokenKind::CmpStr(_) => "push compressed string".to_string(),
+            TokenKind::Map(items) => format!("push map of {}", items.len() / 2),
+        };
+        out.push_str(&format!("{:<width$}  # {}\n", token_text(src, token), note, width = width));
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assert_minify() {
+        assert_eq!(minify("2 3 + 11 * 1 +").unwrap(), "2 3+ 11*1+");
+        assert_eq!(minify("X  1\n+").unwrap(), "X1+");
+        assert_eq!(minify("C M I V").unwrap(), "C M I V");
+        assert_eq!(minify("CM D s x").unwrap(), "CM Dsx");
+        assert_eq!(minify("\"a\" \"b\" + 1 .r").unwrap(), "\"a\"\"b\"+ 1.r");
+        assert_eq!(minify("1 - 1").unwrap(), "1- 1");
+        assert_eq!(minify("\"a\\\"\" \"\\\\\" +").unwrap(), "\"a\\\"\"\"\\\\\"+");
+    }
+
+    #[test]
+    fn assert_expand() {
+        let src = "\"Hi\"P CMD 1.5+, #t";
+        let expanded = expand(src).unwrap();
+        let tokens: Vec<&str> = expanded.lines().map(|l| l.split("  # ").next().unwrap().trim_end()).collect();
+        assert_eq!(tokens, vec!["\"Hi\"", "P", "CMD", "1.5", "+", ",", "#t"]);
+        assert!(expanded.lines().next().unwrap().ends_with("# push string"));
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::VecDeque;
+use std::io::{self, prelude::*};
+
+/// Where a `Putt` program's output goes and its input comes from
+pub trait Host: Send {
+    fn write(&mut self, text: &str) -> io::Result<()>;
+    /// Next character of input, `None` at the end of it
+    fn read_char(&mut self) -> io::Result<Option<char>>;
+    /// Put `c` back, so it's the next character read
+    fn unread(&mut self, c: char);
+}
+
+/// The process' own `stdout` and `stdin`
+#[derive(Default)]
+pub struct StdHost {
+    /// Rest of the last line read from `stdin`
+    pending: VecDeque<char>,
+}
+
+impl Host for StdHost {
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        let mut stdout = io::stdout();
+        stdout.write_all(text.as_bytes())?;
+        stdout.flush()
+    }
+
+    fn read_char(&mut self) -> io::Result<Option<char>> {
+        if self.pending.is_empty() {
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line)?;
+            self.pending.extend(line.chars());
+        }
+        Ok(self.pending.pop_front())
+    }
+
+    fn unread(&mut self, c: char) {
+        self.pending.push_front(c);
+    }
+}
+
+/// Reads from a fixed input and keeps the output, for embedding and tests
+#[derive(Default)]
+pub struct BufferHost {
+    pub input: VecDeque<char>,
+    pub output: String,
+}
+
+impl BufferHost {
+    pub fn new(input: &str) -> Self {
+        BufferHost { input: input.chars().collect(), output: String::new() }
+    }
+}
+
+impl Host for BufferHost {
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        self.output.push_str(text);
+        Ok(())
+    }
+
+    fn read_char(&mut self) -> io::Result<Option<char>> {
+        Ok(self.input.pop_front())
+    }
+
+    fn unread(&mut self, c: char) {
+        self.input.push_front(c);
+    }
+}
+
+/// Passes everything through to another host, keeping a copy of what was written
+pub struct Recorder<'a> {
+    pub inner: &'a mut dyn Host,
+    pub written: String,
+}
+
+impl<'a> Recorder<'a> {
+    pub fn new(inner: &'a mut dyn Host) -> Self {
+        Recorder { inner, written: String::new() }
+    }
+}
+
+impl Host for Recorder<'_> {
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        self.written.push_str(text);
+        self.inner.write(text)
+    }
+
+    fn read_char(&mut self) -> io::Result<Option<char>> {
+        self.inner.read_char()
+    }
+
+    fn unread(&mut self, c: char) {
+        self.inner.unread(c)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Putt;
+    use std::thread;
+
+    fn run(src: &str, input: &str) -> String {
+        let mut putt = Putt::with_host(BufferHost::new(input));
+        putt.parse(src).unwrap();
+        putt.eval_expression().unwrap();
+        putt.finish().unwrap();
+        putt.host.output
+    }
+
+    #[test]
+    fn assert_buffered_io() {
+        assert_eq!(run("\"hi\", 1 2+P", ""), "hi\n3 ");
+        assert_eq!(run(".i.i+", "ab"), "ab\n");
+        assert_eq!(run(".i.l", ""), "0\n");
+    }
+
+    #[test]
+    fn assert_concurrent_programs() {
+        let handles: Vec<_> = (0..8)
+            .map(|i| thread::spawn(move || run(&format!("{} 1+,", i), "")))
+            .collect();
+
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.join().unwrap(), format!("{}\n", i + 1));
+        }
+    }
+}
@@ -0,0 +1,424 @@
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_till},
+    character::complete::{char as ch, digit1, one_of},
+    combinator::{map, opt, recognize},
+    error::VerboseError,
+    sequence::{delimited, pair, tuple},
+    IResult,
+};
+
+use super::*;
+
+// Language tags
+const TRUE: &str = "#t";
+const FALSE: &str = "#f";
+
+/// Starts a two character builtin, e.g. `.r`. Roman numerals and the other
+/// tokens never start with it, so this space can grow freely, as long as the
+/// second character is never a digit: `.5` is a number
+pub const PREFIX: char = '.';
+
+/// A byte range into the source a token was read from
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// 1-based line and column of the start of this span in `src`. Spans that don't
+    /// fit `src`, say from a hand edited snapshot, count from the character they fall in.
+    pub fn line_col(&self, src: &str) -> (usize, usize) {
+        let mut start = self.start.min(src.len());
+        while !src.is_char_boundary(start) {
+            start -= 1;
+        }
+        let before = &src[..start];
+        let line = before.matches('\n').count() + 1;
+        let col = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+        (line, col)
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum TokenKind {
+    /// Decimal literal, e.g. `1.5`
+    Float(Float),
+    /// Roman numeral literal, e.g. `CMD`
+    Roman(u64),
+    /// `#t` or `#f`
+    Bool(bool),
+    /// `"string literal"`
+    Str(String),
+    /// \`compressed string literal\`, still compressed
+    CmpStr(String),
+    /// `{key value ...}` map literal, keys and values being literals themselves
+    Map(Vec<TokenKind>),
+    BuiltIn(BuiltIn),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ParseErrorKind {
+    UnexpectedChar(char),
+    UnknownBuiltIn(String),
+    UnterminatedString,
+    BadCompressedString,
+    BadMap,
+    UnterminatedMap,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.kind {
+            ParseErrorKind::UnexpectedChar(c) => write!(f, "unexpected character {:?}", c),
+            ParseErrorKind::UnknownBuiltIn(sym) => write!(f, "unknown builtin `{}`", sym),
+            ParseErrorKind::UnterminatedString => write!(f, "unterminated string"),
+            ParseErrorKind::BadCompressedString => write!(f, "invalid compressed string"),
+            ParseErrorKind::UnterminatedMap => write!(f, "unterminated map"),
+            ParseErrorKind::BadMap => write!(f, "a map is `{{key value ...}}`, with only literals inside"),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+type LexResult<'a> = IResult<&'a str, TokenKind, VerboseError<&'a str>>;
+
+/// The error a lexer gives when its kind of token doesn't start here
+fn no_match<'a>() -> nom::Err<VerboseError<&'a str>> {
+    nom::Err::Error(VerboseError { errors: vec![] })
+}
+
+/// Builtins from the registry, always taking the longest symbol so `cmp` isn't read as `c`
+fn lex_builtin(i: &str) -> LexResult<'_> {
+    match builtins::longest_match(i) {
+        Some(def) => Ok((&i[def.symbol.len()..], TokenKind::BuiltIn(def.builtin))),
+        None => Err(no_match()),
+    }
+}
+
+fn lex_bool(i: &str) -> LexResult<'_> {
+    alt((
+        map(tag(TRUE), |_| TokenKind::Bool(true)),
+        map(tag(FALSE), |_| TokenKind::Bool(false)),
+    ))(i)
+}
+
+/// `\"`, `\\` and `\n` are escapes, any other backslash is kept as it is
+fn lex_string(i: &str) -> LexResult<'_> {
+    let rest = i.strip_prefix('"').ok_or_else(no_match)?;
+    let mut st = String::new();
+    let mut chars = rest.char_indices().peekable();
+
+    while let Some((at, c)) = chars.next() {
+        match (c, chars.peek().map(|&(_, next)| next)) {
+            ('"', _) => return Ok((&rest[at + 1..], TokenKind::Str(st))),
+            ('\\', Some(next @ ('"' | '\\'))) => {
+                chars.next();
+                st.push(next);
+            }
+            ('\\', Some('n')) => {
+                chars.next();
+                st.push('\n');
+            }
+            (c, _) => st.push(c),
+        }
+    }
+
+    Err(no_match())
+}
+
+/// `st` as a string literal that `lex_string` reads back as `st`
+pub fn escape(st: &str) -> String {
+    format!("\"{}\"", st.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+}
+
+fn lex_com_string(i: &str) -> LexResult<'_> {
+    map(delimited(ch('`'), take_till(|c| c == '`'), ch('`')), |s: &str| {
+        TokenKind::CmpStr(s.to_string())
+    })(i)
+}
+
+/// A sign directly in front of the digits belongs to the number, so `1+2` is two numbers.
+/// Covers what nom's `double` did: either side of the `.` may be left out (`.5`, `1.`) and
+/// an exponent may follow (`1.e5`), except that a bare `.` starting a builtin, as in `1.r`,
+/// is left for the builtin
+fn lex_float(i: &str) -> LexResult<'_> {
+    let (rest, mantissa) = recognize(pair(
+        opt(one_of("+-")),
+        alt((recognize(pair(digit1, opt(pair(ch('.'), digit1)))), recognize(pair(ch('.'), digit1)))),
+    ))(i)?;
+
+    let rest = match rest.strip_prefix('.') {
+        Some(after) if !mantissa.contains('.') && builtins::longest_match(rest).is_none() => after,
+        _ => rest,
+    };
+    let (rest, _) = opt(tuple((one_of("eE"), opt(one_of("+-")), digit1)))(rest)?;
+    let text = &i[..i.len() - rest.len()];
+
+    Ok((rest, TokenKind::Float(text.parse().unwrap())))
+}
+
+/// Length of the longest run of roman numerals at the start of `i`
+fn roman_len(i: &str) -> usize {
+    match parser::NUMERALS.iter().find(|num| i.starts_with(num.symbol)) {
+        Some(num) => num.symbol.len() + roman_len(&i[num.symbol.len()..]),
+        None => 0,
+    }
+}
+
+/// Only consumes characters that are part of a numeral, so `Xd` is `10` then dupe
+fn lex_roman(i: &str) -> LexResult<'_> {
+    match roman_len(i) {
+        0 => Err(no_match()),
+        n => Ok((&i[n..], TokenKind::Roman(parser::from_roman(&i[..n])))),
+    }
+}
+
+/// Pairs of literals between braces, e.g. `{"a" 1 "b" 2}`. Running out of input
+/// before the closing brace is `Incomplete`, so it can be told apart from a bad map.
+fn lex_map(i: &str) -> LexResult<'_> {
+    let mut rest = i.strip_prefix('{').ok_or_else(no_match)?;
+    let mut items = Vec::new();
+
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return Err(nom::Err::Incomplete(nom::Needed::Unknown));
+        }
+        if let Some(next) = rest.strip_prefix('}') {
+            return match items.len() % 2 {
+                0 => Ok((next, TokenKind::Map(items))),
+                _ => Err(no_match()),
+            };
+        }
+        let (next, kind) = match lex_literal(rest) {
+            // an unterminated string runs to the end, taking the closing brace with it
+            Err(nom::Err::Error(_)) if rest.starts_with(['"', '`']) => {
+                return Err(nom::Err::Incomplete(nom::Needed::Unknown))
+            }
+            result => result?,
+        };
+        items.push(kind);
+        rest = next;
+    }
+}
+
+fn lex_literal(i: &str) -> LexResult<'_> {
+    alt((lex_float, lex_bool, lex_com_string, lex_string, lex_map, lex_roman))(i)
+}
+
+fn lex_token(i: &str) -> LexResult<'_> {
+    alt((
+        lex_float,
+        lex_bool,
+        lex_com_string,
+        lex_string,
+        lex_map,
+        lex_builtin,
+        lex_roman,
+    ))(i)
+}
+
+/// Split `src` into tokens, skipping whitespace
+pub fn tokenize(src: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut rest = src.trim_start();
+
+    while !rest.is_empty() {
+        let start = src.len() - rest.len();
+        match lex_token(rest) {
+            Ok((next, kind)) => {
+                let end = src.len() - next.len();
+                tokens.push(Token { kind, span: Span::new(start, end) });
+                rest = next.trim_start();
+            }
+            Err(nom::Err::Incomplete(_)) => {
+                return Err(ParseError { kind: ParseErrorKind::UnterminatedMap, span: Span::new(start, src.len()) });
+            }
+            Err(_) => {
+                let c = rest.chars().next().unwrap();
+                let (kind, end) = match c {
+                    '"' | '`' => (ParseErrorKind::UnterminatedString, src.len()),
+                    '{' => {
+                        let end = rest.find('}').map_or(src.len(), |close| start + close + 1);
+                        (ParseErrorKind::BadMap, end)
+                    }
+                    PREFIX if rest.chars().nth(1).is_some() => {
+                        let sym: String = rest.chars().take(2).collect();
+                        let end = start + sym.len();
+                        (ParseErrorKind::UnknownBuiltIn(sym), end)
+                    }
+                    _ => (ParseErrorKind::UnexpectedChar(c), start + c.len_utf8()),
+                };
+                return Err(ParseError { kind, span: Span::new(start, end) });
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(src: &str) -> Vec<TokenKind> {
+        tokenize(src).unwrap().into_iter().map(|t| t.kind).collect()
+    }
+
+    #[test]
+    fn assert_spans() {
+        let tokens = tokenize("X  \"hi\"cmp").unwrap();
+        let spans: Vec<Span> = tokens.iter().map(|t| t.span).collect();
+        assert_eq!(spans, vec![Span::new(0, 1), Span::new(3, 7), Span::new(7, 10)]);
+        assert_eq!(Span::new(4, 5).line_col("1 2\n34+"), (2, 1));
+        assert_eq!(Span::new(2, 3).line_col("\"é\""), (1, 2));
+        assert_eq!(Span::new(9, 9).line_col("1"), (1, 2));
+    }
+
+    #[test]
+    fn assert_roman_stops_at_builtins() {
+        assert_eq!(
+            kinds("Xd"),
+            vec![TokenKind::Roman(10), TokenKind::BuiltIn(BuiltIn::Dupe)]
+        );
+        assert_eq!(
+            kinds("CMDsx"),
+            vec![
+                TokenKind::Roman(1400),
+                TokenKind::BuiltIn(BuiltIn::Swap),
+                TokenKind::BuiltIn(BuiltIn::Drop)
+            ]
+        );
+        assert_eq!(
+            kinds("Mk:"),
+            vec![TokenKind::Roman(1_000_000), TokenKind::BuiltIn(BuiltIn::Range)]
+        );
+    }
+
+    #[test]
+    fn assert_numbers() {
+        assert_eq!(
+            kinds("1.5 10-"),
+            vec![TokenKind::Float(1.5), TokenKind::Float(10.0), TokenKind::BuiltIn(BuiltIn::Minus)]
+        );
+        assert_eq!(kinds("-2"), vec![TokenKind::Float(-2.0)]);
+        assert_eq!(
+            kinds(".5 1. 2.+"),
+            vec![TokenKind::Float(0.5), TokenKind::Float(1.0), TokenKind::Float(2.0), TokenKind::BuiltIn(BuiltIn::Plus)]
+        );
+        assert_eq!(kinds("1.r"), vec![TokenKind::Float(1.0), TokenKind::BuiltIn(BuiltIn::Reverse)]);
+    }
+
+    /// Every form nom's `double` read
+    #[test]
+    fn assert_float_forms() {
+        assert_eq!(kinds(".5"), vec![TokenKind::Float(0.5)]);
+        assert_eq!(kinds("-.5"), vec![TokenKind::Float(-0.5)]);
+        assert_eq!(kinds("1."), vec![TokenKind::Float(1.0)]);
+        assert_eq!(kinds("1.e5"), vec![TokenKind::Float(1e5)]);
+        assert_eq!(kinds("1e5"), vec![TokenKind::Float(1e5)]);
+        assert_eq!(kinds("2.5E-3"), vec![TokenKind::Float(2.5e-3)]);
+        assert_eq!(kinds("+.5e+2"), vec![TokenKind::Float(50.0)]);
+        assert_eq!(kinds("1.5.r"), vec![TokenKind::Float(1.5), TokenKind::BuiltIn(BuiltIn::Reverse)]);
+    }
+
+    #[test]
+    fn assert_prefixed_builtins() {
+        assert_eq!(
+            kinds("1.5.r X.l"),
+            vec![
+                TokenKind::Float(1.5),
+                TokenKind::BuiltIn(BuiltIn::Reverse),
+                TokenKind::Roman(10),
+                TokenKind::BuiltIn(BuiltIn::Length)
+            ]
+        );
+        assert_eq!(
+            kinds("1.c c"),
+            vec![
+                TokenKind::Float(1.0),
+                TokenKind::BuiltIn(BuiltIn::Ceil),
+                TokenKind::BuiltIn(BuiltIn::Clear)
+            ]
+        );
+        assert_eq!(
+            tokenize("1 .q").unwrap_err(),
+            ParseError { kind: ParseErrorKind::UnknownBuiltIn(".q".to_string()), span: Span::new(2, 4) }
+        );
+    }
+
+    #[test]
+    fn assert_errors() {
+        assert_eq!(
+            tokenize("1 \"abc").unwrap_err(),
+            ParseError { kind: ParseErrorKind::UnterminatedString, span: Span::new(2, 6) }
+        );
+        assert_eq!(
+            tokenize("1 q").unwrap_err(),
+            ParseError { kind: ParseErrorKind::UnexpectedChar('q'), span: Span::new(2, 3) }
+        );
+        assert_eq!(
+            tokenize("{1 2 3} 4").unwrap_err(),
+            ParseError { kind: ParseErrorKind::BadMap, span: Span::new(0, 7) }
+        );
+        assert_eq!(tokenize("{1 +}").unwrap_err().kind, ParseErrorKind::BadMap);
+        assert_eq!(
+            tokenize("1 {1 {2").unwrap_err(),
+            ParseError { kind: ParseErrorKind::UnterminatedMap, span: Span::new(2, 7) }
+        );
+        assert_eq!(tokenize("{1 \"}").unwrap_err().kind, ParseErrorKind::UnterminatedMap);
+    }
+
+    #[test]
+    fn assert_escapes() {
+        let st = "say \"hi\"\n\\ \\q";
+        assert_eq!(escape(st), "\"say \\\"hi\\\"\\n\\\\ \\\\q\"");
+        assert_eq!(kinds(&escape(st)), vec![TokenKind::Str(st.to_string())]);
+        assert_eq!(kinds("\"a\\b\""), vec![TokenKind::Str("a\\b".to_string())]);
+        // these used to stay as two characters each
+        assert_eq!(kinds("\"a\\\\b\""), vec![TokenKind::Str("a\\b".to_string())]);
+        assert_eq!(kinds("\"\\n\""), vec![TokenKind::Str("\n".to_string())]);
+        assert_eq!(tokenize("\"a\\\"").unwrap_err().kind, ParseErrorKind::UnterminatedString);
+    }
+
+    #[test]
+    fn assert_maps() {
+        assert_eq!(
+            kinds("{\"a\" 1\n {} #t}d"),
+            vec![
+                TokenKind::Map(vec![
+                    TokenKind::Str("a".to_string()),
+                    TokenKind::Float(1.0),
+                    TokenKind::Map(vec![]),
+                    TokenKind::Bool(true)
+                ]),
+                TokenKind::BuiltIn(BuiltIn::Dupe)
+            ]
+        );
+    }
+}
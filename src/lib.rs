@@ -1,19 +1,29 @@
 use std::error::Error;
-
-const DEBUG: bool = false;
-
-use nom::error::VerboseError;
+use std::io::Write;
+use std::time::{Duration, Instant};
 
 use parser::*;
+use lexer::Span;
 use smaz::{compress,decompress};
 
+pub mod lexer;
 pub mod parser;
 pub mod atom;
+pub mod builtins;
+pub mod explain;
+pub mod formatter;
+pub mod host;
+pub mod compiler;
+pub mod optimizer;
+pub mod profile;
+pub mod state;
 
 pub type Num = i128;
 pub type Float = f64;
 
 use atom::*;
+use host::{Host, Recorder, StdHost};
+use compiler::{Op, Program};
 
 
 #[derive(Debug, PartialEq, Clone)] pub enum Expr {
@@ -27,89 +37,486 @@ use atom::*;
 }
 
 
-pub struct Putt {
+pub struct Putt<H = StdHost> {
     /// Memory of Putt VM
     pub stack: Vec<Atom>,
 
     pub src: Option<Expr>,
+    /// Source span of each instruction in `src`
+    pub src_spans: Vec<Span>,
 
     /// Instructions
     pub inst: Vec<Atom>,
+    /// Source span of each instruction in `inst`
+    pub spans: Vec<Span>,
+    /// `inst` compiled, this is what actually runs
+    pub code: Program,
     /// Program counter, used for jumps
-    pub pc: usize
+    pub pc: usize,
+    /// Where the chunk being run starts in `inst`
+    pub chunk: usize,
+    /// Where every chunk starts in `inst`, oldest first
+    pub chunks: Vec<usize>,
+
+    /// What to print once a chunk has run
+    pub output: OutputPolicy,
+    /// Whether the chunk being run printed anything
+    pub printed: bool,
+    /// Whether the implicit output is written as JSON
+    pub json: bool,
+
+    /// Where output goes and input comes from
+    pub host: H,
+
+    /// Whether to fold constants before compiling
+    pub optimize: bool,
+
+    /// How much a chunk is allowed to do
+    pub limits: Limits,
+    /// Instructions run so far in this chunk
+    pub executed: u64,
+    /// When this chunk started running
+    pub started: Option<Instant>,
+
+    /// Where to write a JSON line for every instruction run, if anywhere
+    pub trace: Option<Box<dyn Write + Send>>,
+    /// Counts and timings of every instruction run, if profiling
+    pub profile: Option<profile::Profile>,
+}
+
+/// How far a program may go before it is stopped, `None` means no limit
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Limits {
+    pub max_instructions: Option<u64>,
+    pub max_stack: Option<usize>,
+    /// Most characters in a string or atoms in an array
+    pub max_size: Option<usize>,
+    pub timeout: Option<Duration>,
+}
+
+impl Limits {
+    /// Whether something of `size` is too big
+    pub fn too_big(&self, size: usize) -> bool {
+        self.max_size.is_some_and(|max| size > max)
+    }
+}
+
+/// What gets printed once a program has run, on top of what it printed itself
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OutputPolicy {
+    /// The top of the stack
+    Top,
+    /// Every value on the stack, bottom first
+    Stack,
+    Nothing,
+    /// The top of the stack, unless the last instruction of the chunk prints
+    Auto,
+    /// The top of the stack, unless the program printed something
+    IfSilent,
+}
+
+impl std::str::FromStr for OutputPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "top" => Ok(OutputPolicy::Top),
+            "stack" => Ok(OutputPolicy::Stack),
+            "none" => Ok(OutputPolicy::Nothing),
+            "auto" => Ok(OutputPolicy::Auto),
+            "silent" => Ok(OutputPolicy::IfSilent),
+            _ => Err(format!("unknown output policy {:?}, expected top, stack, none, auto or silent", s)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum RuntimeErrorKind {
+    /// A builtin got values it can't work with
+    Type(String),
+    /// Jump past the end of the chunk
+    BadJump(usize),
+    BadCompressedString,
+    /// The host couldn't read or write
+    Io(String),
+    BadJson(String),
+    InstructionLimit(u64),
+    StackLimit(usize),
+    SizeLimit(usize),
+    Timeout(Duration),
+}
+
+impl RuntimeErrorKind {
+    /// Whether this is one of the `Limits` running out, rather than the program going wrong
+    pub fn is_limit(&self) -> bool {
+        matches!(
+            self,
+            RuntimeErrorKind::InstructionLimit(_)
+                | RuntimeErrorKind::StackLimit(_)
+                | RuntimeErrorKind::SizeLimit(_)
+                | RuntimeErrorKind::Timeout(_)
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    /// Source of the failing instruction
+    pub span: Span,
+    /// Index of the failing instruction, counted from the start of its chunk like `J` does
+    pub pc: usize,
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.kind {
+            RuntimeErrorKind::Type(msg) => write!(f, "{}", msg),
+            RuntimeErrorKind::BadJump(target) => write!(f, "can't jump to instruction {}", target),
+            RuntimeErrorKind::BadCompressedString => write!(f, "invalid compressed string"),
+            RuntimeErrorKind::Io(msg) => write!(f, "{}", msg),
+            RuntimeErrorKind::BadJson(msg) => write!(f, "invalid JSON: {}", msg),
+            RuntimeErrorKind::InstructionLimit(max) => write!(f, "ran more than {} instructions", max),
+            RuntimeErrorKind::StackLimit(max) => write!(f, "stack grew past {} values", max),
+            RuntimeErrorKind::SizeLimit(max) => write!(f, "string or array grew past {}", max),
+            RuntimeErrorKind::Timeout(max) => write!(f, "ran longer than {:?}", max),
+        }
+    }
+}
+
+impl Error for RuntimeError {}
+
+/// Everything needed to put a `Putt` back the way it was
+#[derive(Debug, PartialEq, Clone)]
+pub struct Snapshot {
+    pub stack: Vec<Atom>,
+    pub inst: Vec<Atom>,
+    pub spans: Vec<Span>,
+    pub code: Program,
+    pub pc: usize,
+    pub chunk: usize,
+    pub chunks: Vec<usize>,
+}
+
+impl Default for Putt {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Putt {
-    /// Create a new Putt VM
+    /// Create a new Putt VM using `stdout` and `stdin`
     pub fn new() -> Self {
+        Putt::with_host(StdHost::default())
+    }
+}
+
+impl<H: Host> Putt<H> {
+    /// Create a new Putt VM doing its I/O through `host`
+    pub fn with_host(host: H) -> Self {
         Putt {
             stack: Vec::new(),
             src: None,
+            src_spans: Vec::new(),
             inst: Vec::new(),
-            pc: 0
+            spans: Vec::new(),
+            code: Program::default(),
+            pc: 0,
+            chunk: 0,
+            chunks: Vec::new(),
+            output: OutputPolicy::Auto,
+            printed: false,
+            json: false,
+            host,
+            optimize: true,
+            limits: Limits::default(),
+            executed: 0,
+            started: None,
+            trace: None,
+            profile: None,
+        }
+    }
+
+    /// Copy of the current VM state
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            stack: self.stack.clone(),
+            inst: self.inst.clone(),
+            spans: self.spans.clone(),
+            code: self.code.clone(),
+            pc: self.pc,
+            chunk: self.chunk,
+            chunks: self.chunks.clone(),
         }
     }
 
+    /// Go back to the state `snapshot` was taken in
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.stack = snapshot.stack;
+        self.inst = snapshot.inst;
+        self.spans = snapshot.spans;
+        self.code = snapshot.code;
+        self.pc = snapshot.pc;
+        self.chunk = snapshot.chunk;
+        self.chunks = snapshot.chunks;
+    }
+
+    /// Parse `src`, replacing the current source expression
     pub fn parse(&mut self, src: &str) -> Result<(), Box<dyn Error>> {
-        parse_expr(src)
-            .map_err(|e: nom::Err<VerboseError<&str>>| format!("{:#?}", e))
-            .and_then(|(_, exp)| { 
-                self.src = Some(exp); 
-                if DEBUG {
-                    println!("Src: {:?}", self.src);
+        let (exp, spans) = parse_program(src)?;
+        self.src = Some(exp);
+        self.src_spans = spans;
+
+        Ok(())
+    }
+
+    /// Whether everything up to the end of the instructions has run
+    pub fn done(&self) -> bool {
+        self.pc >= self.code.ops.len()
+    }
+
+    /// Whether running another instruction would go past the limits
+    fn check_limits(&self) -> Result<(), RuntimeErrorKind> {
+        let limits = &self.limits;
+
+        if let Some(max) = limits.max_instructions {
+            if self.executed >= max {
+                return Err(RuntimeErrorKind::InstructionLimit(max));
+            }
+        }
+        if let (Some(max), Some(started)) = (limits.timeout, self.started) {
+            if started.elapsed() > max {
+                return Err(RuntimeErrorKind::Timeout(max));
+            }
+        }
+        if let Some(max) = limits.max_stack {
+            if self.stack.len() > max {
+                return Err(RuntimeErrorKind::StackLimit(max));
+            }
+        }
+        // sizing walks the whole value, so only do it when there is a limit
+        if let (Some(max), Some(atom)) = (limits.max_size, self.stack.last()) {
+            if atom.size() > max {
+                return Err(RuntimeErrorKind::SizeLimit(max));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run the instruction at `pc`
+    pub fn step(&mut self) -> Result<(), RuntimeError> {
+        let at = self.pc;
+        let op = self.code.ops[at];
+        let span = self.code.spans[at];
+        let pc = at - self.chunk;
+        let err = |kind| RuntimeError { kind, span, pc };
+
+        self.check_limits().map_err(err)?;
+        self.executed += 1;
+        self.pc += 1;
+
+        let before = self.trace.as_ref().map(|_| self.stack.clone());
+        let timer = self.profile.as_ref().map(|_| Instant::now());
+        let mut output = String::new();
+        let result = self.execute(op, &mut output);
+        if let (Some(profile), Some(timer)) = (&mut self.profile, timer) {
+            profile.record(at, timer.elapsed());
+        }
+        if let Some(before) = before {
+            self.write_trace(at, before, &output, &result).map_err(err)?;
+        }
+        result.map_err(err)?;
+
+        // catch whatever this instruction grew before anything else runs
+        self.check_limits().map_err(err)
+    }
+
+    /// Carry out `op`, keeping what it printed in `output` while tracing
+    fn execute(&mut self, op: Op, output: &mut String) -> Result<(), RuntimeErrorKind> {
+        match op {
+            Op::Push(n) => self.stack.push(self.code.consts[n].clone()),
+            Op::Jump { target, .. } => self.pc = target,
+            Op::Call(bi) => {
+                if let BuiltIn::Print | BuiltIn::PrintLn | BuiltIn::PrintRepr = bi {
+                    self.printed = true;
                 }
-                Ok(())
-            })?;
 
+                // builtins see the pc relative to the chunk, so jumps stay inside it
+                let mut pc = self.pc - self.chunk;
+
+                if self.trace.is_some() {
+                    let mut host = Recorder::new(&mut self.host);
+                    let result = bi.call(&mut self.stack, &mut pc, &mut host, &self.limits);
+                    output.push_str(&host.written);
+                    result?;
+                } else {
+                    bi.call(&mut self.stack, &mut pc, &mut self.host, &self.limits)?;
+                }
+                if self.chunk + pc > self.code.ops.len() {
+                    return Err(RuntimeErrorKind::BadJump(pc));
+                }
+                self.pc = self.chunk + pc;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the trace line for instruction `at`, which ran on `before` and printed `output`
+    fn write_trace(
+        &mut self,
+        at: usize,
+        before: Vec<Atom>,
+        output: &str,
+        result: &Result<(), RuntimeErrorKind>,
+    ) -> Result<(), RuntimeErrorKind> {
+        let json = |stack: &[Atom]| stack.iter().map(Atom::to_json).collect::<Vec<_>>();
+        let span = self.code.spans[at];
+
+        let op = match self.code.ops[at] {
+            Op::Push(_) => "push",
+            Op::Jump { .. } => "jump",
+            Op::Call(_) => "call",
+        };
+
+        let mut line = serde_json::json!({
+            "pc": at,
+            "op": op,
+            "inst": self.code.instruction(at).to_json(),
+            "span": [span.start, span.end],
+            "before": json(&before),
+            "after": json(&self.stack),
+            "output": output,
+        });
+        if let Err(kind) = result {
+            let msg = RuntimeError { kind: kind.clone(), span, pc: at }.to_string();
+            line["error"] = serde_json::Value::String(msg);
+        }
+
+        if let Some(trace) = &mut self.trace {
+            writeln!(trace, "{}", line).map_err(|e| RuntimeErrorKind::Io(e.to_string()))?;
+        }
         Ok(())
+    }
+
+    /// What `output` says to print now that the chunk has run, if anything
+    pub fn implicit_output(&self) -> Option<String> {
+        let top = || match (self.stack.last(), self.json) {
+            (Some(atom), true) => atom.to_json().to_string(),
+            (Some(atom), false) => format!("{}", atom),
+            (None, true) => "null".to_string(),
+            (None, false) => "[]".to_string(),
+        };
+
+        match self.output {
+            OutputPolicy::Top => Some(top()),
+            OutputPolicy::Auto => match self.code.ops[self.chunk..].last() {
+                Some(Op::Call(BuiltIn::Print | BuiltIn::PrintLn | BuiltIn::PrintRepr)) => None,
+                _ => Some(top()),
+            },
+            OutputPolicy::IfSilent if !self.printed => Some(top()),
+            OutputPolicy::Stack if self.json => {
+                Some(serde_json::Value::Array(self.stack.iter().map(Atom::to_json).collect()).to_string())
+            }
+            OutputPolicy::Stack if self.stack.is_empty() => Some("[]".to_string()),
+            OutputPolicy::Stack => Some(
+                self.stack
+                    .iter()
+                    .map(|atom| format!("{}", atom))
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+            ),
+            _ => None,
+        }
+    }
 
+    /// Print the implicit output, if any, through the host
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        match self.implicit_output() {
+            Some(out) => self.host.write(&format!("{}\n", out)),
+            None => Ok(()),
+        }
     }
 
-    /// This function tries to reduce the AST.
-    /// This has to return an Expression rather than an Atom because quoted s_expressions
-    /// can't be reduced
-    pub fn eval_expression(&mut self) -> Result<(), &str> {
-        if let Some(e) = self.src.clone() {
-            match e {
-                Expr::Function(head) => {
-                    for atom in head {
-                        self.inst.push(atom);
+    /// Compile the parsed source, if there is any. The parser only ever gives back a
+    /// function, the other kinds of expression have nothing to compile to yet.
+    pub fn compile(&self) -> Option<Program> {
+        match &self.src {
+            Some(Expr::Function(head)) if self.optimize => {
+                let (head, spans) = optimizer::fold_constants(head, &self.src_spans);
+                Some(compiler::compile(&head, &spans))
+            }
+            Some(Expr::Function(head)) => Some(compiler::compile(head, &self.src_spans)),
+            _ => None,
+        }
+    }
+
+    /// Append the parsed source to the instructions as a new chunk and run it.
+    /// Jumps are relative to the start of the chunk.
+    pub fn eval_expression(&mut self) -> Result<(), RuntimeError> {
+        match self.compile() {
+            Some(program) => self.eval_program(&program),
+            None => Ok(()),
+        }
+    }
 
-                        if DEBUG {
-                            println!("Stack Dump: {:?}", self.stack);
-                        }
+    /// Append an already compiled program as a new chunk and run it
+    pub fn eval_program(&mut self, program: &Program) -> Result<(), RuntimeError> {
+        self.load_program(program);
+        self.resume()
+    }
 
-                        let top = self.inst.get_mut(self.pc).unwrap().clone();
-                        if let Atom::BuiltIn(bi) = top {
-                            bi.call(&mut self.stack, &mut self.pc);
-                            self.pc += 1;
-                        } else {
-                            self.stack.push(top);
-                            self.pc += 1;
-                        }
+    /// Keep running from `pc` to the end, e.g. after restoring a snapshot of a program
+    /// that was stopped by a limit. The limits count from now.
+    pub fn resume(&mut self) -> Result<(), RuntimeError> {
+        self.executed = 0;
+        self.started = Some(Instant::now());
 
-                    }
-                },
-                _ => unreachable!()
-            };
+        while !self.done() {
+            self.step()?;
         }
 
         Ok(())
     }
+
+    /// Append an already compiled program as a new chunk, ready to `step` through
+    pub fn load_program(&mut self, program: &Program) {
+        self.chunk = self.code.ops.len();
+        self.chunks.push(self.chunk);
+        self.pc = self.chunk;
+        self.printed = false;
+        self.executed = 0;
+        self.started = Some(Instant::now());
+        self.inst.extend(program.instructions());
+        self.spans.extend(program.spans.iter().cloned());
+        self.code.extend(program);
+    }
 }
 
 
+/// `num * (num - 1) * ...` down to where it truncates to 0 or 1, so `2.5!` is `2.5`
 fn factorial(num: Float) -> Float {
-    match num as usize {
-        0 => 1.0,
-        1 => 1.0,
-        _ => factorial(num - 1.0) * num,
+    let mut total = 1.0;
+    let mut n = num;
+    while n as usize > 1 {
+        total *= n;
+        // no need to keep going once it doesn't fit
+        if total.is_infinite() {
+            break;
+        }
+        n -= 1.0;
     }
+    total
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Putt, Atom};
+    use super::{parser, BuiltIn, Expr, Putt, Atom, Limits, OutputPolicy, RuntimeErrorKind, Span};
+    use crate::host::BufferHost;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
 
     macro_rules! putt_eq {
         ($p:expr,$e:expr) => {
@@ -134,6 +541,258 @@ mod tests {
         putt_eq!("2 3+11*1+", Atom::Float(34.0));
         putt_eq!("\"Hi\"\"Hello!\"+", Atom::Str(String::from("HiHello!")));
         putt_eq!("6!", Atom::Float(720.0));
+        putt_eq!("3.5!", Atom::Float(8.75));
+        putt_eq!("1 5:.l", Atom::Float(5.0));
+        putt_eq!("1.5.f", Atom::Float(1.0));
+        putt_eq!("1.5.c", Atom::Float(2.0));
+        putt_eq!("\"abc\".r", Atom::Str(String::from("cba")));
+        putt_eq!("\"[1.5, [true], null]\".j.J", Atom::Str(String::from("[1.5,[1],0]")));
+    }
+
+    /// Restoring a snapshot undoes everything run since
+    #[test]
+    fn test_snapshot() {
+        let mut putt = Putt::new();
+        putt.parse("1 2").unwrap();
+        putt.eval_expression().unwrap();
+        let snapshot = putt.snapshot();
+
+        putt.parse("+d").unwrap();
+        putt.eval_expression().unwrap();
+        assert_eq!(putt.stack, vec![Atom::Float(3.0), Atom::Float(3.0)]);
+
+        putt.restore(snapshot.clone());
+        assert_eq!(putt.snapshot(), snapshot);
+        assert_eq!(putt.pc, 2);
+    }
+
+    /// Each parse and eval is its own chunk, jumps stay inside it
+    #[test]
+    fn test_chunks() {
+        let mut putt = Putt::new();
+        putt.parse("1 2").unwrap();
+        putt.eval_expression().unwrap();
+        putt.parse("4J 9 9 3").unwrap();
+        putt.eval_expression().unwrap();
+        assert_eq!(putt.stack, vec![Atom::Float(1.0), Atom::Float(2.0), Atom::Float(3.0)]);
+        assert_eq!(putt.chunk, 2);
+        assert_eq!(putt.pc, 7);
+
+        putt.parse("1 9J").unwrap();
+        let err = putt.eval_expression().unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::BadJump(9));
+        // counted from the start of the chunk, like the jump itself
+        assert_eq!(err.pc, 2);
+        assert_eq!(putt.chunks, vec![0, 2, 7]);
+    }
+
+    /// Type errors are reported instead of panicking
+    #[test]
+    fn test_type_error() {
+        let mut putt = Putt::new();
+        putt.parse("1 \"a\"*").unwrap();
+        let err = putt.eval_expression().unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::Type("I can't multiply those types".to_string()));
+        assert_eq!(err.span, Span::new(5, 6));
+    }
+
+    /// Implicit output follows the policy and always looks at the stack
+    #[test]
+    fn test_implicit_output() {
+        let output = |src: &str, policy| {
+            let mut putt = Putt::with_host(BufferHost::default());
+            putt.output = policy;
+            putt.parse(src).unwrap();
+            putt.eval_expression().unwrap();
+            putt.implicit_output()
+        };
+
+        assert_eq!(output("1 2+ 5", OutputPolicy::Top), Some("5".to_string()));
+        assert_eq!(output("1 2", OutputPolicy::Stack), Some("1\n2".to_string()));
+        assert_eq!(output("", OutputPolicy::Stack), Some("[]".to_string()));
+        assert_eq!(output("1 2", OutputPolicy::Nothing), None);
+        assert_eq!(output("1 2", OutputPolicy::IfSilent), Some("2".to_string()));
+        assert_eq!(output("1P 2", OutputPolicy::IfSilent), None);
+        assert_eq!(output("\"a\", 5", OutputPolicy::Auto), Some("5".to_string()));
+        assert_eq!(output("5 \"a\",", OutputPolicy::Auto), None);
+        assert_eq!(output("1 2,", OutputPolicy::Top), Some("1".to_string()));
+
+        let json = |src: &str, policy| {
+            let mut putt = Putt::new();
+            putt.output = policy;
+            putt.json = true;
+            putt.parse(src).unwrap();
+            putt.eval_expression().unwrap();
+            putt.implicit_output()
+        };
+        assert_eq!(json("\"a\" 1 2:", OutputPolicy::Stack), Some("[\"a\",[1,2]]".to_string()));
+        assert_eq!(json("", OutputPolicy::Top), Some("null".to_string()));
+    }
+
+    /// Every limit stops the program with its own error
+    #[test]
+    fn test_limits() {
+        let run = |src: &str, limits| {
+            let mut putt = Putt::new();
+            putt.limits = limits;
+            putt.parse(src).unwrap();
+            putt.eval_expression().map_err(|e| e.kind)
+        };
+        let unlimited = Limits::default();
+
+        assert_eq!(
+            run("0J", Limits { max_instructions: Some(100), ..unlimited }),
+            Err(RuntimeErrorKind::InstructionLimit(100))
+        );
+        assert_eq!(
+            run("0J", Limits { timeout: Some(Duration::from_millis(10)), ..unlimited }),
+            Err(RuntimeErrorKind::Timeout(Duration::from_millis(10)))
+        );
+        assert_eq!(
+            run("1 1 1", Limits { max_stack: Some(2), ..unlimited }),
+            Err(RuntimeErrorKind::StackLimit(2))
+        );
+        assert_eq!(
+            run("0 Mk Mk*:", Limits { max_size: Some(1000), ..unlimited }),
+            Err(RuntimeErrorKind::SizeLimit(1000))
+        );
+        assert_eq!(
+            run("\"ab\"d+d+d+", Limits { max_size: Some(10), ..unlimited }),
+            Err(RuntimeErrorKind::SizeLimit(10))
+        );
+        assert_eq!(run("Mk! Mk Mk*S", unlimited), Ok(()));
+    }
+
+    /// A compiled program can be run again without parsing it
+    #[test]
+    fn test_eval_program() {
+        let mut putt = Putt::new();
+        putt.parse("1+").unwrap();
+        let program = putt.compile().unwrap();
+        putt.parse("0").unwrap();
+        putt.eval_expression().unwrap();
+        for _ in 0..3 {
+            putt.eval_program(&program).unwrap();
+        }
+        assert_eq!(putt.stack, vec![Atom::Float(3.0)]);
+        assert_eq!(putt.inst.len(), 7);
+    }
+
+    /// Tracing writes one JSON line per instruction run
+    #[test]
+    fn test_trace() {
+        #[derive(Clone, Default)]
+        struct Shared(Arc<Mutex<Vec<u8>>>);
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let out = Shared::default();
+        let mut putt = Putt::with_host(BufferHost::default());
+        putt.trace = Some(Box::new(out.clone()));
+        putt.optimize = false;
+        putt.parse("1 2+d, \"a\"*").unwrap();
+        assert!(putt.eval_expression().is_err());
+
+        let trace = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = trace.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(
+            lines[2],
+            serde_json::json!({"pc": 2, "op": "call", "inst": "+", "span": [3, 4], "before": [1, 2], "after": [3], "output": ""})
+        );
+        assert_eq!(lines[4]["output"], "3\n");
+        assert_eq!(lines[6]["error"], "I can't multiply those types");
+    }
+
+    /// JSON objects come in as maps
+    #[test]
+    fn test_json_objects() {
+        let value = serde_json::json!({"b": [false], "a": {}});
+        let key = |k: &str| Atom::Str(k.to_string());
+        assert_eq!(
+            Atom::from_json(&value),
+            Atom::Map(vec![(key("a"), Atom::Map(vec![])), (key("b"), Atom::Arr(vec![Atom::Float(0.0)]))])
+        );
+        putt_eq!("{\"a\" 1} \"b\" 2.s.J", Atom::Str(String::from("{\"a\":1,\"b\":2}")));
+    }
+
+    /// Test maps
+    #[test]
+    fn test_maps() {
+        let map = |src: &str| {
+            let mut putt = Putt::new();
+            putt.parse(src).unwrap();
+            putt.eval_expression().unwrap();
+            putt.stack.pop().unwrap()
+        };
+        // the order keys were written in doesn't matter
+        assert_eq!(map("{\"b\" 2 \"a\" 1}"), map("{} \"a\" 1.s \"b\" 2.s"));
+        assert_eq!(map("{\"b\" 2 \"a\" 1}").to_string(), "{a: 1, b: 2}");
+        assert_eq!(map("{1 {} X #t}").to_string(), "{1: {}, 10: 1}");
+
+        putt_eq!("{\"a\" 1} \"a\".g", Atom::Float(1.0));
+        putt_eq!("{\"a\" 1} \"b\".g", Atom::Float(0.0));
+        putt_eq!("{\"a\" 1} \"a\".h", Atom::Float(1.0));
+        putt_eq!("{1 2 3 4} 1.d", map("{3 4}"));
+        putt_eq!("{1 2 3 4}.k", Atom::Arr(vec![Atom::Float(1.0), Atom::Float(3.0)]));
+        putt_eq!("{1 2 3 4}.v", Atom::Arr(vec![Atom::Float(2.0), Atom::Float(4.0)]));
+        putt_eq!("{1 2 3 4} {3 5 6 7}.m", map("{1 2 3 5 6 7}"));
+        putt_eq!("{1 2 3 4}.l", Atom::Float(2.0));
+        putt_eq!("{1 2} {1 2}=", Atom::Float(1.0));
+
+        let mut putt = Putt::new();
+        putt.parse("1 2.g").unwrap();
+        assert!(matches!(putt.eval_expression().unwrap_err().kind, RuntimeErrorKind::Type(_)));
+    }
+
+    /// Test repr, which shows what type everything is
+    #[test]
+    fn test_repr() {
+        let nested = Atom::Arr(vec![Atom::Str("a \"b\"\n".to_string()), Atom::Arr(vec![Atom::Float(1.5)])]);
+        assert_eq!(nested.repr(), "[\"a \\\"b\\\"\\n\" [1.5]]");
+        assert_eq!(nested.to_string(), "a \"b\"\n 1.5");
+        assert_eq!(Atom::BuiltIn(BuiltIn::Plus).repr(), "+");
+        assert_eq!(Atom::BuiltIn(BuiltIn::Plus).to_string(), "+");
+
+        // maps and flat values read back as they were
+        let mut putt = Putt::new();
+        putt.parse("{\"a\" {1 #t} 2 \"x y\"}").unwrap();
+        putt.eval_expression().unwrap();
+        let map = putt.stack.pop().unwrap();
+        assert_eq!(map.repr(), "{2 \"x y\" \"a\" {1 1}}");
+        assert_eq!(parser::parse_expr(&map.repr()).unwrap(), Expr::Function(vec![map]));
+        let st = Atom::Str("say \"hi\"\n".to_string());
+        assert_eq!(parser::parse_expr(&st.repr()).unwrap(), Expr::Function(vec![st]));
+
+        let mut putt = Putt::with_host(BufferHost::default());
+        putt.output = OutputPolicy::IfSilent;
+        putt.parse("\"1\".p 1 2:.p 3").unwrap();
+        putt.eval_expression().unwrap();
+        assert_eq!(putt.host.output, "\"1\"\n[1 2]\n");
+        assert_eq!(putt.implicit_output(), None);
+    }
+
+    /// Test which atoms survive a trip through repr and back
+    #[test]
+    fn test_repr_round_trip() {
+        let reads_back = |atom: &Atom| parser::parse_expr(&atom.repr()).ok() == Some(Expr::Function(vec![atom.clone()]));
+        let map = Atom::Map(vec![(Atom::Float(-2.5), Atom::Str(String::new())), (Atom::Str("k".to_string()), Atom::Float(1e20))]);
+        for atom in &[Atom::Float(0.125), Atom::Float(-3.0), Atom::Str("a\\b\n\"c\"".to_string()), Atom::BuiltIn(BuiltIn::Sum), map] {
+            assert!(reads_back(atom), "{} didn't read back", atom.repr());
+        }
+
+        let arr = Atom::Arr(vec![Atom::Float(1.0)]);
+        let map_of_arr = Atom::Map(vec![(Atom::Float(1.0), arr.clone())]);
+        let map_of_builtin = Atom::Map(vec![(Atom::Float(1.0), Atom::BuiltIn(BuiltIn::Plus))]);
+        for atom in &[arr, map_of_arr, map_of_builtin, Atom::Float(f64::NAN), Atom::Float(f64::INFINITY), Atom::Float(f64::NEG_INFINITY)] {
+            assert!(!reads_back(atom), "{} read back", atom.repr());
+        }
     }
 
     /// Test string
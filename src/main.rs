@@ -1,19 +1,214 @@
-use putt::atom::*;
 use putt::*;
-use std::{error::Error, fs::File, io, io::prelude::*, path::PathBuf};
+use putt::lexer::ParseError;
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{self, prelude::*},
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
 use structopt::StructOpt;
 
+mod debugger;
+mod repl;
+
 #[derive(StructOpt, Debug)]
 #[structopt(name = "putt")]
 struct PuttCLI {
     #[structopt(name = "FILE")]
     /// File to read
     path: Option<PathBuf>,
+
+    #[structopt(long = "list-builtins")]
+    /// Print a table of every builtin and exit
+    list_builtins: bool,
+
+    #[structopt(long = "explain")]
+    /// Explain FILE one instruction per line instead of running it
+    explain: bool,
+
+    #[structopt(long = "minify")]
+    /// Print FILE with all removable whitespace stripped instead of running it
+    minify: bool,
+
+    #[structopt(long = "expand")]
+    /// Print FILE one annotated token per line instead of running it
+    expand: bool,
+
+    #[structopt(long = "disasm")]
+    /// Print the bytecode FILE compiles to instead of running it
+    disasm: bool,
+
+    #[structopt(long = "debug")]
+    /// Step through FILE in a debugger instead of just running it
+    debug: bool,
+
+    #[structopt(long = "trace")]
+    /// Write a JSON line to stderr for every instruction run
+    trace: bool,
+
+    #[structopt(long = "profile")]
+    /// Print how often each builtin and source position ran, and for how long, to stderr
+    profile: bool,
+
+    #[structopt(long = "checkpoint")]
+    /// If the program fails or a limit stops it, save where it got to in this file
+    checkpoint: Option<PathBuf>,
+
+    #[structopt(long = "resume")]
+    /// Carry on running a program saved with --checkpoint instead of running FILE
+    resume: Option<PathBuf>,
+
+    #[structopt(long = "no-optimize")]
+    /// Run instructions exactly as written, without folding constants first
+    no_optimize: bool,
+
+    #[structopt(long = "output", default_value = "auto")]
+    /// What to print once the program has run: top, stack, none, auto (the top of
+    /// the stack unless the program ends by printing) or silent (the top of the stack
+    /// unless the program printed anything)
+    output: OutputPolicy,
+
+    #[structopt(long = "json")]
+    /// Write the implicit output as JSON
+    json: bool,
+
+    #[structopt(long = "input-json")]
+    /// Push the JSON document in this file (- for stdin) onto the stack before running
+    input_json: Option<PathBuf>,
+
+    #[structopt(long = "max-instructions")]
+    /// Stop a program after it has run this many instructions
+    max_instructions: Option<u64>,
+
+    #[structopt(long = "max-stack")]
+    /// Stop a program once its stack holds more than this many values
+    max_stack: Option<usize>,
+
+    #[structopt(long = "max-size")]
+    /// Stop a program once a string or array on its stack grows past this size
+    max_size: Option<usize>,
+
+    #[structopt(long = "timeout", parse(try_from_str = "parse_seconds"))]
+    /// Stop a program after it has run this many seconds
+    timeout: Option<Duration>,
+}
+
+/// A number of seconds, which may have a fraction
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let secs: f64 = s.parse().map_err(|_| format!("{:?} isn't a number of seconds", s))?;
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("{:?} isn't a duration a program can run for", s))
+}
+
+/// Show `e` along with the line and column in `src` it came from
+fn report(src: &str, e: &(dyn Error + 'static)) {
+    let span = e
+        .downcast_ref::<ParseError>()
+        .map(|e| e.span)
+        .or_else(|| e.downcast_ref::<RuntimeError>().map(|e| e.span));
+
+    match span {
+        Some(span) => {
+            let (line, col) = span.line_col(src);
+            eprintln!("Error at {}:{}: {}", line, col, e)
+        }
+        None => eprintln!("Error: {}", e),
+    }
+}
+
+/// Save the VM state along with the source it came from, so it can be resumed
+fn checkpoint(putt: &Putt, src: &str, path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut json = state::to_json(&putt.snapshot());
+    json["source"] = src.into();
+    fs::write(path, json.to_string())?;
+    Ok(())
+}
+
+/// Report how running `src` went, and print the implicit output if it went well
+fn conclude(
+    mut putt: Putt,
+    src: &str,
+    result: Result<(), Box<dyn Error>>,
+    checkpoint_to: Option<&Path>,
+) -> Result<(), Box<dyn Error>> {
+    // a program that failed or got stopped is just as worth profiling
+    if let Some(profile) = &putt.profile {
+        eprint!("{}", profile.report(&putt.code, src));
+    }
+
+    if let Err(e) = result {
+        report(src, &*e);
+        // a program that didn't parse never got anywhere worth saving
+        if let (Some(e), Some(path)) = (e.downcast_ref::<RuntimeError>(), checkpoint_to) {
+            if e.kind.is_limit() {
+                checkpoint(&putt, src, path)?;
+                eprintln!("Saved the program's state to {}, carry on with --resume", path.display());
+            } else {
+                // point at the instruction that failed rather than the one after it
+                putt.pc = putt.chunk + e.pc;
+                checkpoint(&putt, src, path)?;
+                eprintln!("Saved the program's state to {}, look at it with :restore in the REPL", path.display());
+            }
+        }
+        process::exit(1);
+    }
+
+    putt.finish()?;
+    Ok(())
+}
+
+/// Parse `src` and run it as a new chunk
+fn run(putt: &mut Putt, src: &str) -> Result<(), Box<dyn Error>> {
+    putt.parse(src)?;
+    putt.eval_expression()?;
+    Ok(())
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let puttcli = PuttCLI::from_args();
     let mut putt = Putt::new();
+    putt.output = puttcli.output;
+    putt.optimize = !puttcli.no_optimize;
+    putt.json = puttcli.json;
+    if puttcli.profile {
+        putt.profile = Some(profile::Profile::default());
+    }
+    if puttcli.trace {
+        putt.trace = Some(Box::new(io::stderr()));
+    }
+    putt.limits = Limits {
+        max_instructions: puttcli.max_instructions,
+        max_stack: puttcli.max_stack,
+        max_size: puttcli.max_size,
+        timeout: puttcli.timeout,
+    };
+
+    if puttcli.list_builtins {
+        print!("{}", builtins::markdown_table());
+        return Ok(());
+    }
+
+    if let Some(path) = &puttcli.input_json {
+        let text = if path.as_os_str() == "-" {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            text
+        } else {
+            fs::read_to_string(path)?
+        };
+        let value: serde_json::Value = serde_json::from_str(&text)?;
+        putt.stack.push(atom::Atom::from_json(&value));
+    }
+
+    if let Some(path) = &puttcli.resume {
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+        putt.restore(state::from_json(&json)?);
+        let src = json["source"].as_str().unwrap_or("").to_string();
+
+        let result = putt.resume().map_err(Box::from);
+        return conclude(putt, &src, result, puttcli.checkpoint.as_deref());
+    }
 
     // Load file or open REPL
     if let Some(path) = puttcli.path {
@@ -21,60 +216,37 @@ fn main() -> Result<(), Box<dyn Error>> {
         let mut fstring = String::new();
         file.read_to_string(&mut fstring)?;
 
-        putt.parse(&fstring)?;
-        putt.eval_expression()?;
-
-        // Inject printing code at the end
-        if let Some(atom) = putt.inst.last() {
-            match atom {
-                Atom::BuiltIn(b) => match b {
-                    BuiltIn::Print => {}
-                    BuiltIn::PrintLn => {}
-                    _ => println!(
-                        "{}",
-                        match putt.stack.last() {
-                            Some(atom) => format!("{}", atom),
-                            None => "[]".to_string(),
-                        }
-                    ),
-                },
-                _ => println!("{}", atom),
-            }
+        if puttcli.explain {
+            print!("{}", explain::explain(&fstring)?);
+            return Ok(());
         }
 
-    } else {
-        println!("PUTT REPL v0.0.1");
-        print!(">> ");
-        io::stdout().flush()?;
-
-        let stdin = io::stdin();
-        // Start reading lines
-        for line in stdin.lock().lines() {
-            putt.parse(&line.unwrap())?;
-
-            putt.eval_expression()?;
-
-            // Inject printing code at the end
-            if let Some(atom) = putt.inst.last() {
-                match atom {
-                    Atom::BuiltIn(b) => match b {
-                        BuiltIn::Print => {}
-                        BuiltIn::PrintLn => {}
-                        _ => println!(
-                            "{}",
-                            match putt.stack.last() {
-                                Some(atom) => format!("{}", atom),
-                                None => "[]".to_string(),
-                            }
-                        ),
-                    },
-                    _ => println!("{}", atom),
-                }
+        if puttcli.minify {
+            println!("{}", formatter::minify(&fstring)?);
+            return Ok(());
+        }
+
+        if puttcli.expand {
+            print!("{}", formatter::expand(&fstring)?);
+            return Ok(());
+        }
+
+        if puttcli.disasm {
+            putt.parse(&fstring)?;
+            if let Some(program) = putt.compile() {
+                print!("{}", compiler::disassemble(&program, &fstring));
             }
+            return Ok(());
+        }
 
-            print!("\n>> ");
-            io::stdout().flush()?;
+        if puttcli.debug {
+            return debugger::run(putt, &fstring);
         }
+
+        let result = run(&mut putt, &fstring);
+        conclude(putt, &fstring, result, puttcli.checkpoint.as_deref())?;
+    } else {
+        repl::run(putt)?;
     }
 
     Ok(())
@@ -0,0 +1,171 @@
+use super::*;
+use host::BufferHost;
+
+/// Biggest string or array folding may leave in the constant pool
+const MAX_FOLDED_SIZE: usize = 256;
+
+/// Whether `bi` always gives the same result for the same operands, without touching
+/// anything but the values it pops
+fn is_pure(bi: BuiltIn) -> bool {
+    use BuiltIn::*;
+    matches!(
+        bi,
+        Plus | Minus | Times | Divide | Equal | Power | Root | Modulus | Factorial | Negate | Abs | Range
+            | Floor | Ceil | Reverse | Length | Not | Cmp | Dcmp | FromJson | ToJson
+            | Get | Set | Delete | Keys | Values | HasKey | Merge
+    )
+}
+
+/// An instruction left after folding and where the first instruction it stands for was
+struct Folded {
+    atom: Atom,
+    span: Span,
+    start: usize,
+}
+
+/// Where every `J` in `atoms` can go, or `None` if any of them can go somewhere
+/// only known at run time
+fn jump_targets(atoms: &[Atom]) -> Option<Vec<usize>> {
+    let mut targets = Vec::new();
+
+    for (i, atom) in atoms.iter().enumerate() {
+        if *atom != Atom::BuiltIn(BuiltIn::Jmp) {
+            continue;
+        }
+        match i.checked_sub(1).map(|prev| &atoms[prev]) {
+            Some(Atom::Float(f)) if (*f as usize) <= atoms.len() => targets.push(*f as usize),
+            _ => return None,
+        }
+    }
+
+    // a `J` that is itself jumped to pops whatever happens to be on the stack
+    if atoms.iter().enumerate().any(|(i, atom)| *atom == Atom::BuiltIn(BuiltIn::Jmp) && targets.contains(&i)) {
+        return None;
+    }
+
+    Some(targets)
+}
+
+/// Run `bi` on `operands` the way the VM would, if that gives exactly one small value
+fn fold(bi: BuiltIn, operands: Vec<Atom>) -> Option<Atom> {
+    let mut stack = operands;
+    let limits = Limits { max_size: Some(MAX_FOLDED_SIZE), ..Limits::default() };
+
+    // errors are left for the VM to report when the instruction runs
+    bi.call(&mut stack, &mut 0, &mut BufferHost::default(), &limits).ok()?;
+    match stack.pop() {
+        Some(atom) if stack.is_empty() && !limits.too_big(atom.size()) => Some(atom),
+        _ => None,
+    }
+}
+
+/// Evaluate pure builtins whose operands are all literals ahead of time, so `800 10*`
+/// becomes `8000`. Folding never reaches across an instruction a jump lands on, and
+/// jumps are moved to where their targets end up. Chunks with jumps whose target is
+/// only known at run time are left alone, since any instruction could be one.
+pub fn fold_constants(atoms: &[Atom], spans: &[Span]) -> (Vec<Atom>, Vec<Span>) {
+    let targets = match jump_targets(atoms) {
+        Some(targets) => targets,
+        None => return (atoms.to_vec(), spans.to_vec()),
+    };
+
+    let mut out: Vec<Folded> = Vec::new();
+    for (i, (atom, span)) in atoms.iter().zip(spans).enumerate() {
+        let bi = match atom {
+            Atom::BuiltIn(bi) if is_pure(*bi) => *bi,
+            _ => {
+                out.push(Folded { atom: atom.clone(), span: *span, start: i });
+                continue;
+            }
+        };
+
+        let pops = bi.def().pops;
+        let first = match out.len().checked_sub(pops) {
+            Some(first) if pops > 0 => first,
+            _ => {
+                out.push(Folded { atom: atom.clone(), span: *span, start: i });
+                continue;
+            }
+        };
+
+        let literals = out[first..].iter().all(|f| !matches!(f.atom, Atom::BuiltIn(_)));
+        let start = out[first].start;
+        let landed_on = targets.iter().any(|&t| start < t && t <= i);
+
+        let folded = if literals && !landed_on {
+            fold(bi, out[first..].iter().map(|f| f.atom.clone()).collect())
+        } else {
+            None
+        };
+
+        match folded {
+            Some(value) => {
+                let span = Span::new(out[first].span.start, span.end);
+                out.truncate(first);
+                out.push(Folded { atom: value, span, start });
+            }
+            None => out.push(Folded { atom: atom.clone(), span: *span, start: i }),
+        }
+    }
+
+    // every target is the start of something left, or the end
+    let moved = |target: usize| out.iter().position(|f| f.start == target).unwrap_or(out.len());
+    let mut folded_atoms: Vec<Atom> = out.iter().map(|f| f.atom.clone()).collect();
+    for i in 1..folded_atoms.len() {
+        if folded_atoms[i] == Atom::BuiltIn(BuiltIn::Jmp) {
+            if let Atom::Float(f) = folded_atoms[i - 1] {
+                folded_atoms[i - 1] = Atom::Float(moved(f as usize) as Float);
+            }
+        }
+    }
+
+    (folded_atoms, out.iter().map(|f| f.span).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folded(src: &str) -> Vec<Atom> {
+        match parser::parse_program(src).unwrap() {
+            (Expr::Function(atoms), spans) => fold_constants(&atoms, &spans).0,
+            _ => unreachable!(),
+        }
+    }
+
+    fn atoms(src: &str) -> Vec<Atom> {
+        match parser::parse_expr(src).unwrap() {
+            Expr::Function(atoms) => atoms,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn assert_folds_literals() {
+        assert_eq!(folded("800 10*"), atoms("8000"));
+        assert_eq!(folded("X 1+ 2*,"), atoms("22,"));
+        assert_eq!(folded("\"ab\".r.l"), atoms("2"));
+        assert_eq!(folded("1 3:"), vec![Atom::Arr(vec![Atom::Float(1.0), Atom::Float(2.0), Atom::Float(3.0)])]);
+    }
+
+    #[test]
+    fn assert_keeps_semantics() {
+        // operands from the stack, errors and huge values are left for run time
+        assert_eq!(folded("1+"), atoms("1+"));
+        assert_eq!(folded("1 \"a\"*"), atoms("1 \"a\"*"));
+        assert_eq!(folded("0 Mk:"), atoms("0 Mk:"));
+        assert_eq!(folded("1 d+"), atoms("1 d+"));
+    }
+
+    #[test]
+    fn assert_respects_jumps() {
+        // the jump lands on the `3`, so `2 3+` can't become `5`
+        assert_eq!(folded("1 4J 2 3+ 4 5*"), atoms("1 4J 2 3+ 20"));
+        assert_eq!(folded("1 3J 2 3+"), atoms("1 3J 5"));
+        // targets move along with what was folded away
+        assert_eq!(folded("1 2+ 5J 6 7"), atoms("3 3J 6 7"));
+        assert_eq!(folded("1 2+ 5J"), atoms("3 3J"));
+        // jumps only known at run time could land anywhere
+        assert_eq!(folded("1 1+J 2 3+"), atoms("1 1+J 2 3+"));
+    }
+}
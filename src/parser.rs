@@ -1,53 +1,15 @@
-use smaz::{decompress};
-use std::io::prelude::*;
-use nom::{
-    branch::alt,
-    bytes::complete::{is_not, tag},
-    character::complete::{alpha1, char as ch, digit1, multispace0, multispace1, one_of},
-    number::complete::{double},
-    combinator::{cut, map, map_res, opt},
-    error::{context, VerboseError},
-    multi::many0,
-    sequence::{delimited, preceded, terminated, tuple},
-    IResult,
-};
-
-// Language tags
-const TRUE: &str = "#t";
-const FALSE: &str = "#f";
-const NOT: &str = "n";
-const JMP: &str = "J";
-
-const ABS: &str = "A";
-const NEG: &str = "N";
-const RANGE: &str = ":";
-const SUM: &str = "S";
-const AVG: &str = "G";
-
-const PRINTLN: &str = ",";
-const PRINT: &str = "P";
-
-const LEN: &str = "~";
-const SWAP: &str = "s";
-const DUPE: &str = "d";
-const CLEAR: &str = "c";
-const DROP: &str = "x";
-
-const CMP: &str = "cmp";
-const DCMP: &str = "dmp";
-
-const IF: &str = "?";
-const _ELSE: &str = "|";
+use smaz::decompress;
 
 use super::*;
+use lexer::*;
 
 pub struct RomanNumeral {
-    symbol: &'static str,
-    value: u64
+    pub symbol: &'static str,
+    pub value: u64
 }
 
- 
-const NUMERALS: [RomanNumeral; 25] = [
+
+pub const NUMERALS: [RomanNumeral; 25] = [
     RomanNumeral {symbol: "Mk",  value: 1_000_000},
     RomanNumeral {symbol: "CMk",  value: 900_000},
     RomanNumeral {symbol: "Dk",  value: 500_000},
@@ -82,169 +44,64 @@ pub fn from_roman(roman: &str) -> u64 {
     }
 }
 
-
-/// Use nom to parse builtin operators
-fn parse_builtin_op<'a>(i: &'a str) -> IResult<&'a str, BuiltIn, VerboseError<&'a str>> {
-    // one_of matches one of the characters we give it
-    let (i, t) = one_of("R+-*/=!^%")(i)?;
-
-    // because we are matching single character tokens, we can do the matching logic
-    // on the returned value
-    Ok((
-            i,
-            match t {
-                '+' => BuiltIn::Plus,
-                '-' => BuiltIn::Minus,
-                '*' => BuiltIn::Times,
-                '/' => BuiltIn::Divide,
-                '=' => BuiltIn::Equal,
-                '!' => BuiltIn::Factorial,
-                '^' => BuiltIn::Power,
-                'R' => BuiltIn::Root,
-                '%' => BuiltIn::Modulus,
-                _ => unreachable!(),
-            },
-            ))
-}
-
-fn parse_builtin<'a>(i: &'a str) -> IResult<&'a str, BuiltIn, VerboseError<&'a str>> {
-    // alt gives us the result of first parser that succeeds, of the series of
-    // parsers we give it
-    delimited(multispace0, alt((
-            parse_builtin_op,
-            // map lets us process the parsed output, in this case we know what we parsed,
-            // so we ignore the input and return the BuiltIn directly
-            map(tag(JMP), |_| BuiltIn::Jmp),
-            map(tag(ABS), |_| BuiltIn::Abs),
-            map(tag(NEG), |_| BuiltIn::Negate),
-            map(tag(LEN), |_| BuiltIn::Len),
-            map(tag(AVG), |_| BuiltIn::Avg),
-            map(tag(SUM), |_| BuiltIn::Sum),
-            map(tag(RANGE), |_| BuiltIn::Range),
-            map(tag(NOT), |_| BuiltIn::Not),
-            map(tag(PRINTLN), |_| BuiltIn::PrintLn),
-            map(tag(PRINT), |_| BuiltIn::Print),
-            map(tag(CMP), |_| BuiltIn::Cmp),
-            map(tag(DCMP), |_| BuiltIn::Dcmp),
-            map(tag(SWAP), |_| BuiltIn::Swap),
-            map(tag(DUPE), |_| BuiltIn::Dupe),
-            map(tag(DROP), |_| BuiltIn::Drop),
-            map(tag(CLEAR), |_| BuiltIn::Clear),
-            )), multispace0)(i)
-}
-
-/// Our boolean values are also constant, so we can do it the same way
-fn parse_bool<'a>(i: &'a str) -> IResult<&'a str, Atom, VerboseError<&'a str>> {
-    alt((map(tag(TRUE), |_| Atom::Float(1.0)), map(tag(FALSE), |_| Atom::Float(0.0))))(i)
-}
-
-/// Parse string literal
-fn parse_string<'a>(i: &'a str) -> IResult<&'a str, Atom, VerboseError<&'a str>> {
-    if super::DEBUG {
-        println!("String parser");
-    }
-    let res = map(context("string", delimited(ch('"'), is_not("\""), ch('"'))), |sym_str: &str| {
-        Atom::Str(sym_str.to_string())
-    })(i);
-    res
-}
-
-/// Parse and decompress a smaz encoded string
-fn parse_com_string<'a>(i: &'a str) -> IResult<&'a str, Atom, VerboseError<&'a str>> {
-    if super::DEBUG {
-        println!("String parser");
-    }
-    let res = map(context("string", delimited(ch('`'), is_not("`"), ch('`'))), |sym_str: &str| {
-        Atom::Str(String::from_utf8(decompress(sym_str.as_bytes()).unwrap()).unwrap())
-    })(i);
-    res
+/// Turn a single token into the atom it pushes (or calls)
+fn parse_token(token: &Token) -> Result<Atom, ParseError> {
+    parse_kind(&token.kind, token.span)
 }
 
-/// Parse roman numeral literal
-fn parse_roman<'a>(i: &'a str) -> IResult<&'a str, Atom, VerboseError<&'a str>> {
-    map(context("roman numeral", preceded(multispace0, alpha1)), |numeral: &str| {
-        let num = from_roman(numeral) as Num;
-        if super::DEBUG {
-            println!("Roman: {} Hindu: {}", numeral, num);
+fn parse_kind(kind: &TokenKind, span: Span) -> Result<Atom, ParseError> {
+    Ok(match kind {
+        TokenKind::Float(f) => Atom::Float(*f),
+        TokenKind::Roman(num) => Atom::Float(*num as Float),
+        TokenKind::Bool(b) => Atom::Float(if *b { 1.0 } else { 0.0 }),
+        TokenKind::Str(s) => Atom::Str(s.clone()),
+        TokenKind::CmpStr(s) => decompress(s.as_bytes())
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .map(Atom::Str)
+            .ok_or(ParseError { kind: ParseErrorKind::BadCompressedString, span })?,
+        // the lexer only lets literals in, in pairs
+        TokenKind::Map(items) => {
+            let atoms = items.iter().map(|kind| parse_kind(kind, span)).collect::<Result<Vec<_>, _>>()?;
+            let mut map = Vec::new();
+            for pair in atoms.chunks(2) {
+                atom::map_insert(&mut map, pair[0].clone(), pair[1].clone());
+            }
+            Atom::Map(map)
         }
-        Atom::Float(num as f64)
-    })(i)
+        TokenKind::BuiltIn(bi) => Atom::BuiltIn(*bi),
+    })
 }
 
-// /// Parse an integer, either singed or unsigned
-// fn parse_num<'a>(i: &'a str) -> IResult<&'a str, Atom, VerboseError<&'a str>> {
-//     alt((
-//             map_res(digit1, |digit_str: &str| digit_str.parse::<Num>().map(Atom::Num)),
-//             map(preceded(tag("-"), digit1), |digit_str: &str| {
-//                 Atom::Num(-1 * digit_str.parse::<Num>().unwrap())
-//             }),
-//             ))(i)
-// }
-
-/// Parse a floating point number
-fn parse_float<'a>(i: &'a str) -> IResult<&'a str, Atom, VerboseError<&'a str>> {
-    alt((
-            map(double, |f: f64| Atom::Float(f)),
-            map(preceded(tag("-"), double), |digit_str: f64| {
-                Atom::Float(-1.0 * digit_str)
-            }),
-            ))(i)
-}
-
-/// Parse atomics
-fn parse_atom<'a>(i: &'a str) -> IResult<&'a str, Atom, VerboseError<&'a str>> {
-    // TODO: Delimite floating points
-    preceded(multispace0, alt((parse_float,parse_bool, parse_com_string, parse_string, map(parse_builtin, Atom::BuiltIn), parse_roman, )))(i)
+/// Parse a token stream, every token becomes exactly one instruction
+pub fn parse_tokens(tokens: &[Token]) -> Result<Expr, ParseError> {
+    tokens
+        .iter()
+        .map(parse_token)
+        .collect::<Result<Vec<Atom>, ParseError>>()
+        .map(Expr::Function)
 }
 
+/// Parse source into an expression along with the span of each of its instructions
+pub fn parse_program(src: &str) -> Result<(Expr, Vec<Span>), ParseError> {
+    let tokens = tokenize(src)?;
+    let spans = tokens.iter().map(|t| t.span).collect();
 
-/// Parse expressions
-fn parse_func<'a>(i: &'a str) -> IResult<&'a str, Expr, VerboseError<&'a str>> {
-  let application_inner = map(preceded(multispace0, many0(parse_atom)), |head| {
-    Expr::Function(head)
-  });
-  // finally, we wrap it in an s-expression
-  application_inner(i)
+    Ok((parse_tokens(&tokens)?, spans))
 }
 
-// TODO: If/else statments
-fn parse_if<'a>(i: &'a str) -> IResult<&'a str, Expr, VerboseError<&'a str>> {
-  let if_inner = context(
-    "if expression",
-    map(
-      preceded(
-        // here to avoid ambiguity with other names starting with `if`, if we added
-        // variables to our language, we say that if must be terminated by at least
-        // one whitespace character
-        terminated(tag(IF), multispace1),
-        cut(tuple((parse_expr, parse_expr, opt(parse_expr)))),
-      ),
-      |(pred, true_branch, maybe_false_branch)| {
-        if let Some(false_branch) = maybe_false_branch {
-          Expr::IfElse(Box::new(pred), Box::new(true_branch), Box::new(false_branch))
-        } else {
-          Expr::If(Box::new(pred), Box::new(true_branch))
-        }
-      },
-    ),
-  );
-  if_inner(i)
-}
-
-
 /// We tie them all together again, making a top-level expression parser!
-
-pub fn parse_expr<'a>(i: &'a str) -> IResult<&'a str, Expr, VerboseError<&'a str>> {
-  preceded(multispace0, alt((parse_func, parse_if)))(i)
+pub fn parse_expr(src: &str) -> Result<Expr, ParseError> {
+    parse_program(src).map(|(exp, _)| exp)
 }
 
 
 
 #[cfg(test)]
 mod tests {
-    macro_rules! nom_eq {
+    macro_rules! parse_eq {
     ($p:expr,$e:expr) => (
-        assert_eq!($p.unwrap().1, $e)
+        assert_eq!(parse_expr($p).unwrap(), Expr::Function(vec![$e]))
     )
     }
 
@@ -260,21 +117,33 @@ mod tests {
     use super::*;
     #[test]
     fn assert_parse_string() {
-        nom_eq!(parse_string("\"Hello, World!\""), atom_str("Hello, World!"));
-        nom_eq!(parse_string("\"Hello, World\""), atom_str("Hello, World"));
+        parse_eq!("\"Hello, World!\"", atom_str("Hello, World!"));
+        parse_eq!("\"Hello, World\"", atom_str("Hello, World"));
     }
 
     #[test]
     fn assert_roman() {
-        nom_eq!(parse_roman("CMD"), atom_num(1400));
-        nom_eq!(parse_roman("CMk"), atom_num(900_000));
-        nom_eq!(parse_roman("Mk"), atom_num(1_000_000));
-        nom_eq!(parse_roman("Dk"), atom_num(500_000));
-        nom_eq!(parse_roman("Lk"), atom_num(50_000));
-        nom_eq!(parse_roman("C"), atom_num(100));
-        nom_eq!(parse_roman("X"), atom_num(10));
-        nom_eq!(parse_roman("V"), atom_num(5));
-        nom_eq!(parse_roman("IV"), atom_num(4));
-        nom_eq!(parse_roman("I"), atom_num(1));
+        parse_eq!("CMD", atom_num(1400));
+        parse_eq!("CMk", atom_num(900_000));
+        parse_eq!("Mk", atom_num(1_000_000));
+        parse_eq!("Dk", atom_num(500_000));
+        parse_eq!("Lk", atom_num(50_000));
+        parse_eq!("C", atom_num(100));
+        parse_eq!("X", atom_num(10));
+        parse_eq!("V", atom_num(5));
+        parse_eq!("IV", atom_num(4));
+        parse_eq!("I", atom_num(1));
+    }
+
+    #[test]
+    fn assert_builtin_letters() {
+        assert_eq!(
+            parse_expr("Xdx").unwrap(),
+            Expr::Function(vec![
+                atom_num(10),
+                Atom::BuiltIn(BuiltIn::Dupe),
+                Atom::BuiltIn(BuiltIn::Drop)
+            ])
+        );
     }
 }
@@ -0,0 +1,132 @@
+use super::*;
+use compiler::{Op, Program};
+use std::collections::HashMap;
+
+/// How many positions the report lists
+const HOTTEST: usize = 20;
+
+/// How often something ran and how long it took altogether
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Entry {
+    pub count: u64,
+    pub time: Duration,
+}
+
+impl Entry {
+    fn add(&mut self, other: Entry) {
+        self.count += other.count;
+        self.time += other.time;
+    }
+}
+
+/// Counts and timings for every instruction run, by its index in `Putt::code`
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Profile {
+    pub ops: Vec<Entry>,
+}
+
+impl Profile {
+    /// Count one run of op `at` that took `time`
+    pub fn record(&mut self, at: usize, time: Duration) {
+        if self.ops.len() <= at {
+            self.ops.resize(at + 1, Entry::default());
+        }
+        self.ops[at].add(Entry { count: 1, time });
+    }
+
+    /// Totals for every builtin that ran, with literals counted as `push` and jumps the
+    /// compiler resolved ahead of time as `jump (static)`, apart from the `J` builtin
+    pub fn by_builtin(&self, code: &Program) -> Vec<(&'static str, Entry)> {
+        let mut totals: HashMap<&'static str, Entry> = HashMap::new();
+        for (op, entry) in code.ops.iter().zip(&self.ops).filter(|(_, e)| e.count > 0) {
+            let name = match op {
+                Op::Call(bi) => bi.def().name,
+                Op::Jump { .. } => "jump (static)",
+                Op::Push(_) => "push",
+            };
+            totals.entry(name).or_default().add(*entry);
+        }
+
+        let mut totals: Vec<_> = totals.into_iter().collect();
+        totals.sort_by(|a, b| b.1.time.cmp(&a.1.time).then(b.1.count.cmp(&a.1.count)).then(a.0.cmp(b.0)));
+        totals
+    }
+
+    /// Every op that ran, by index, slowest first
+    pub fn by_position(&self) -> Vec<(usize, Entry)> {
+        let mut ops: Vec<_> = self.ops.iter().cloned().enumerate().filter(|(_, e)| e.count > 0).collect();
+        ops.sort_by(|a, b| b.1.time.cmp(&a.1.time).then(b.1.count.cmp(&a.1.count)).then(a.0.cmp(&b.0)));
+        ops
+    }
+
+    /// Tables of the time spent per builtin and in the hottest places of `src`
+    pub fn report(&self, code: &Program, src: &str) -> String {
+        let mut out = format!("{:<12} {:>10} {:>12} {:>12}\n", "builtin", "count", "total", "average");
+        for (name, entry) in self.by_builtin(code) {
+            out.push_str(&format!(
+                "{:<12} {:>10} {:>12} {:>12}\n",
+                name,
+                entry.count,
+                format!("{:?}", entry.time),
+                format!("{:?}", entry.time.div_f64(entry.count as f64)),
+            ));
+        }
+
+        out.push_str(&format!("\n{:<8} {:<12} {:>10} {:>12}\n", "at", "source", "count", "total"));
+        for (i, entry) in self.by_position().into_iter().take(HOTTEST) {
+            let span = code.spans[i];
+            let (line, col) = span.line_col(src);
+            out.push_str(&format!(
+                "{:<8} {:<12} {:>10} {:>12}\n",
+                format!("{}:{}", line, col),
+                src.get(span.start..span.end).unwrap_or("").replace('\n', "\\n"),
+                entry.count,
+                format!("{:?}", entry.time),
+            ));
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assert_counts() {
+        let mut putt = Putt::new();
+        putt.profile = Some(Profile::default());
+        putt.optimize = false;
+        // counts down from 3
+        putt.parse("3 d1-d0=XI*1+J").unwrap();
+        putt.eval_expression().unwrap();
+
+        let profile = putt.profile.unwrap();
+        let counts: HashMap<_, _> = profile.by_builtin(&putt.code).into_iter().map(|(n, e)| (n, e.count)).collect();
+        assert_eq!(counts["push"], 13);
+        assert_eq!(counts["dupe"], 6);
+        assert_eq!(counts["jump"], 3);
+        assert_eq!(counts.len(), 7);
+
+        let positions: Vec<_> = profile.by_position().into_iter().map(|(i, e)| (i, e.count)).collect();
+        assert_eq!(positions.len(), 12);
+        assert!(positions.contains(&(0, 1)));
+        assert!(positions.contains(&(11, 3)));
+        assert!(profile.report(&putt.code, "3 d1-d0=XI*1+J").contains("1:3"));
+    }
+
+    #[test]
+    fn assert_static_jumps() {
+        let mut putt = Putt::new();
+        putt.profile = Some(Profile::default());
+        putt.optimize = false;
+        // both jumps are resolved at compile time, skipping the 5 and the 7
+        putt.parse("3J 5 6J 7").unwrap();
+        putt.eval_expression().unwrap();
+
+        let counts: HashMap<_, _> = putt.profile.unwrap().by_builtin(&putt.code).into_iter().map(|(n, e)| (n, e.count)).collect();
+        assert_eq!(counts["jump (static)"], 2);
+        assert!(!counts.contains_key("jump"));
+    }
+}
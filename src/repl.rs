@@ -0,0 +1,551 @@
+use putt::atom::*;
+use putt::lexer::{tokenize, ParseError, ParseErrorKind, Token, TokenKind};
+use putt::*;
+use rustyline::{
+    completion::{Completer, Pair},
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    validate::Validator,
+    CompletionType, Config, Context, Editor, Helper,
+};
+use std::{env, error::Error, fs, path::PathBuf};
+
+const PROMPT: &str = ">> ";
+/// Shown while a line is continued, e.g. inside a string
+const CONTINUE_PROMPT: &str = ".. ";
+
+/// Where history is kept between sessions
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".putt_history"))
+}
+
+/// Whether `src` stops in the middle of a token that a following line could finish
+fn is_incomplete(src: &str) -> bool {
+    matches!(
+        tokenize(src),
+        Err(ParseError { kind: ParseErrorKind::UnterminatedString | ParseErrorKind::UnterminatedMap, .. })
+    )
+}
+
+/// Show every command and builtin, or only the builtin spelled `symbol`
+fn help(symbol: &str) {
+    if symbol.is_empty() {
+        println!("{}\n", COMMANDS);
+        for def in builtins::BUILTINS {
+            println!("{}", def);
+        }
+    } else {
+        match builtins::lookup(symbol) {
+            Some(def) => println!("{}", def),
+            None => println!("No builtin `{}`", symbol),
+        }
+    }
+}
+
+const COMMANDS: &str = "\
+:stack              show the whole stack with types
+:clear              clear the stack
+:reset              start over with a fresh VM
+:inst               show every instruction run so far, by chunk
+:load <file>        run a file in this session
+:undo [n]           undo the last n lines (default 1)
+:redo [n]           redo the last n undone lines (default 1)
+:save <file> [min]  save every line so far as a program, minified with min
+:dump <file>        save the whole session state, stack and all
+:restore <file>     carry on from a session saved with :dump
+:help [symbol]      show help for one or all builtins";
+
+/// Completes builtins, and commands at the start of a line
+struct PuttHelper;
+
+/// Where the word being completed starts in `line[..pos]`, and what it could become
+fn complete_at(line: &str, pos: usize) -> (usize, Vec<Pair>) {
+    let before = &line[..pos];
+
+    if let Some(cmd) = before.strip_prefix(':') {
+        if cmd.contains(char::is_whitespace) {
+            // arguments are file names or symbols, leave them be
+            return (pos, Vec::new());
+        } else {
+            let pairs = COMMANDS
+                .lines()
+                .filter(|l| l[1..].starts_with(cmd))
+                .map(|l| Pair {
+                    display: l.to_string(),
+                    replacement: l.split_whitespace().next().unwrap().to_string(),
+                })
+                .collect();
+            return (0, pairs);
+        }
+    }
+
+    // builtins need no space in front of them, so try the longest tail of the
+    // line that could still grow into a symbol
+    let longest = builtins::BUILTINS.iter().map(|def| def.symbol.len()).max().unwrap_or(0);
+    for len in (0..=longest.min(before.len())).rev() {
+        let start = pos - len;
+        if !before.is_char_boundary(start) {
+            continue;
+        }
+        let prefix = &before[start..];
+        let pairs: Vec<Pair> = builtins::BUILTINS
+            .iter()
+            .filter(|def| def.symbol.starts_with(prefix))
+            .map(|def| Pair {
+                display: format!("{:<4} {:<11} {}", def.symbol, def.name, def.effect),
+                replacement: def.symbol.to_string(),
+            })
+            .collect();
+        if !pairs.is_empty() {
+            return (start, pairs);
+        }
+    }
+
+    (pos, Vec::new())
+}
+
+impl Completer for PuttHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        Ok(complete_at(line, pos))
+    }
+}
+
+impl Hinter for PuttHelper {
+    type Hint = String;
+}
+
+impl Highlighter for PuttHelper {}
+
+impl Validator for PuttHelper {}
+
+impl Helper for PuttHelper {}
+
+/// Show every value on the stack, top last
+fn show_stack(putt: &Putt) {
+    if putt.stack.is_empty() {
+        println!("[]");
+    }
+    for (i, atom) in putt.stack.iter().enumerate() {
+        println!("{:>4}  {:<7} {}", i, atom.type_name(), atom.repr());
+    }
+}
+
+/// The instruction history one chunk at a time, along with where each came from in its
+/// line. Instructions count from the start of their chunk, the way `J` and errors do.
+fn inst_listing(putt: &Putt) -> String {
+    let mut listing = String::new();
+    let ends = putt.chunks.iter().skip(1).copied().chain(Some(putt.inst.len()));
+    for (n, (&start, end)) in putt.chunks.iter().zip(ends).enumerate() {
+        listing += &format!("chunk {} (from {}):\n", n + 1, start);
+        for (i, (atom, span)) in putt.inst[start..end].iter().zip(&putt.spans[start..end]).enumerate() {
+            listing += &format!("{:>4}  {:<7} {:<12} {}\n", i, atom.type_name(), atom.repr(), span);
+        }
+    }
+    listing
+}
+
+/// Everything a line can change: the VM and the lines that got it there
+#[derive(Clone)]
+struct State {
+    vm: Snapshot,
+    lines: Vec<String>,
+}
+
+struct Session {
+    putt: Putt,
+    /// Every line that ran without error, in order
+    lines: Vec<String>,
+    /// States to go back (or forward) to
+    undo: Vec<State>,
+    redo: Vec<State>,
+}
+
+impl Session {
+    fn new(putt: Putt) -> Self {
+        Session { putt, lines: Vec::new(), undo: Vec::new(), redo: Vec::new() }
+    }
+
+    fn state(&self) -> State {
+        State { vm: self.putt.snapshot(), lines: self.lines.clone() }
+    }
+
+    fn restore(&mut self, state: State) {
+        self.putt.restore(state.vm);
+        self.lines = state.lines;
+    }
+
+    /// Remember the state before something changes it
+    fn record(&mut self) {
+        self.undo.push(self.state());
+        self.redo.clear();
+    }
+
+    /// Go back to the last recorded state, without being able to redo it
+    fn discard(&mut self) {
+        if let Some(state) = self.undo.pop() {
+            self.restore(state);
+        }
+    }
+
+    /// Move up to `n` states from undo to redo (or back), returning how many were moved
+    fn travel(&mut self, n: usize, backwards: bool) -> usize {
+        let mut moved = 0;
+        while moved < n {
+            let state = match if backwards { self.undo.pop() } else { self.redo.pop() } {
+                Some(state) => state,
+                None => break,
+            };
+            let current = self.state();
+            if backwards { self.redo.push(current) } else { self.undo.push(current) }
+            self.restore(state);
+            moved += 1;
+        }
+        moved
+    }
+
+    /// Run `src` as a new chunk and show the top of the stack. If anything goes wrong
+    /// the error is reported and the VM goes back to how it was before.
+    fn eval(&mut self, src: &str) {
+        self.record();
+        if let Err(e) = crate::run(&mut self.putt, src) {
+            crate::report(src, &*e);
+            self.discard();
+            return;
+        }
+        self.lines.push(src.to_string());
+
+        if let Err(e) = self.putt.finish() {
+            eprintln!("Error: {}", e);
+        }
+    }
+
+    /// Write every line so far to `path` as one program, minified if asked to. Jumps
+    /// count from the start of their line, so literal targets are moved to where the
+    /// line starts in the file.
+    fn save(&self, path: &str, minify: bool) -> Result<(), Box<dyn Error>> {
+        let mut lines = Vec::new();
+        let mut offset = 0;
+        for line in &self.lines {
+            let tokens = tokenize(line)?;
+            lines.push(relocate_jumps(line, &tokens, offset)?);
+            offset += tokens.len();
+        }
+
+        let mut src = lines.join("\n");
+        if minify {
+            src = formatter::minify(&src)?;
+        }
+        src.push('\n');
+        fs::write(path, src)?;
+
+        Ok(())
+    }
+
+    /// Write the VM and the lines that got it there to `path`
+    fn dump(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut json = state::to_json(&self.putt.snapshot());
+        json["lines"] = self.lines.clone().into();
+        fs::write(path, json.to_string())?;
+        Ok(())
+    }
+
+    /// Read back what `dump` wrote
+    fn read_dump(path: &str) -> Result<State, Box<dyn Error>> {
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let vm = state::from_json(&json)?;
+        let lines = json["lines"]
+            .as_array()
+            .map(|lines| lines.iter().filter_map(|l| l.as_str()).map(String::from).collect())
+            .unwrap_or_default();
+        Ok(State { vm, lines })
+    }
+
+    /// Run a `:command`, reporting anything that goes wrong
+    fn command(&mut self, cmd: &str, arg: &str) {
+        let count = || if arg.is_empty() { Ok(1) } else { arg.parse::<usize>() };
+
+        match cmd {
+            "stack" => show_stack(&self.putt),
+            "clear" => {
+                // the same as running `c`, which is also what gets saved
+                self.eval(BuiltIn::Clear.def().symbol)
+            }
+            "reset" => {
+                self.record();
+                self.restore(State { vm: Putt::new().snapshot(), lines: Vec::new() })
+            }
+            "inst" => print!("{}", inst_listing(&self.putt)),
+            "load" => match fs::read_to_string(arg) {
+                Ok(src) => self.eval(&src),
+                Err(e) => eprintln!("Can't load {:?}: {}", arg, e),
+            },
+            "save" => {
+                let (path, minify) = match arg.strip_suffix("min") {
+                    Some(path) if path.ends_with(char::is_whitespace) => (path.trim(), true),
+                    _ => (arg, false),
+                };
+                match self.save(path, minify) {
+                    Ok(()) => println!("Saved {} line(s) to {}", self.lines.len(), path),
+                    Err(e) => eprintln!("Can't save {:?}: {}", path, e),
+                }
+            }
+            "dump" => match self.dump(arg) {
+                Ok(()) => println!("Saved the session to {}", arg),
+                Err(e) => eprintln!("Can't dump to {:?}: {}", arg, e),
+            },
+            "restore" => match Session::read_dump(arg) {
+                Ok(state) => {
+                    self.record();
+                    self.restore(state);
+                    println!("Restored {} line(s) from {}", self.lines.len(), arg);
+                }
+                Err(e) => eprintln!("Can't restore {:?}: {}", arg, e),
+            },
+            "undo" => match count() {
+                Ok(n) => println!("Undid {} line(s)", self.travel(n, true)),
+                Err(e) => eprintln!("Bad count {:?}: {}", arg, e),
+            },
+            "redo" => match count() {
+                Ok(n) => println!("Redid {} line(s)", self.travel(n, false)),
+                Err(e) => eprintln!("Bad count {:?}: {}", arg, e),
+            },
+            "help" => help(arg),
+            _ => println!("Unknown command :{}\n{}", cmd, COMMANDS),
+        }
+    }
+}
+
+/// `line` with the literal target of every `J` in `tokens` moved `offset` instructions
+/// on, the way it has to be once the line no longer starts the program. Jumps whose
+/// target is only known at run time can't be moved.
+fn relocate_jumps(line: &str, tokens: &[Token], offset: usize) -> Result<String, String> {
+    if offset == 0 {
+        return Ok(line.to_string());
+    }
+
+    let mut out = String::new();
+    let mut copied = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        if token.kind != TokenKind::BuiltIn(BuiltIn::Jmp) {
+            continue;
+        }
+        let target = match i.checked_sub(1).map(|prev| &tokens[prev]) {
+            Some(Token { kind: TokenKind::Float(f), .. }) => *f as usize,
+            Some(Token { kind: TokenKind::Roman(num), .. }) => *num as usize,
+            Some(Token { kind: TokenKind::Bool(b), .. }) => *b as usize,
+            _ => return Err(format!("can't save {:?}, it jumps somewhere only known when it runs", line)),
+        };
+
+        let literal = tokens[i - 1].span;
+        out.push_str(&line[copied..literal.start]);
+        // keep the new number from running into whatever came before it
+        if !out.is_empty() && !out.ends_with(char::is_whitespace) {
+            out.push(' ');
+        }
+        out.push_str(&(target + offset).to_string());
+        copied = literal.end;
+    }
+    out.push_str(&line[copied..]);
+
+    Ok(out)
+}
+
+/// Name of every command, as listed in `COMMANDS`
+fn command_names() -> impl Iterator<Item = &'static str> {
+    COMMANDS.lines().filter_map(|l| l[1..].split_whitespace().next())
+}
+
+/// Split `:name arg` into its name and argument, if `line` is a command at all. The
+/// stack carries over between lines, so a line may well start with the `:` range
+/// builtin; only the names in `COMMANDS` are commands, and none of them lex as code.
+fn parse_command(line: &str) -> Option<(&str, &str)> {
+    let rest = line.trim().strip_prefix(':')?;
+    let (cmd, arg) = rest.split_at(rest.find(char::is_whitespace).unwrap_or(rest.len()));
+    if !command_names().any(|name| name == cmd) {
+        return None;
+    }
+    Some((cmd, arg.trim()))
+}
+
+/// Read a whole input, prompting for more lines while it is incomplete.
+/// Returns `None` at the end of input.
+fn read_input(rl: &mut Editor<PuttHelper>) -> Result<Option<String>, ReadlineError> {
+    let mut input = match rl.readline(PROMPT) {
+        Ok(line) => line,
+        Err(ReadlineError::Eof) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    while is_incomplete(&input) {
+        match rl.readline(CONTINUE_PROMPT) {
+            Ok(line) => {
+                input.push('\n');
+                input.push_str(&line);
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(Some(input))
+}
+
+pub fn run(putt: Putt) -> Result<(), Box<dyn Error>> {
+    println!("PUTT REPL v0.0.1");
+
+    let mut session = Session::new(putt);
+
+    let config = Config::builder().completion_type(CompletionType::List).build();
+    let mut rl = Editor::with_config(config);
+    rl.set_helper(Some(PuttHelper));
+    let history_file = history_path();
+    if let Some(path) = &history_file {
+        // there is no history the first time around
+        let _ = rl.load_history(path);
+    }
+
+    loop {
+        let line = match read_input(&mut rl) {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            // Ctrl-C drops the current line
+            Err(ReadlineError::Interrupted) => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        if !line.trim().is_empty() {
+            rl.add_history_entry(line.as_str());
+        }
+
+        if let Some((cmd, arg)) = parse_command(&line) {
+            session.command(cmd, arg);
+        } else {
+            session.eval(&line);
+        }
+
+        println!();
+    }
+
+    if let Some(path) = &history_file {
+        rl.save_history(path)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assert_inst_counts_from_each_chunk() {
+        let mut session = Session::new(Putt::new());
+        session.putt.output = OutputPolicy::Nothing;
+        session.putt.optimize = false;
+        session.eval("1 2");
+        session.eval("4 5+");
+        let listing = inst_listing(&session.putt);
+        let lines: Vec<_> = listing.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(lines[0], "chunk 1 (from 0):");
+        assert_eq!(lines[3], "chunk 2 (from 2):");
+        assert!(lines[4].starts_with("   0  float   4 "));
+        assert!(lines[6].starts_with("   2  builtin + "));
+    }
+
+    #[test]
+    fn assert_dump_restores() {
+        let path = env::temp_dir().join(format!("putt-dump-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut session = Session::new(Putt::new());
+        session.putt.output = OutputPolicy::Nothing;
+        session.eval("1 2");
+        session.eval("\"a\" 3:");
+        session.dump(path).unwrap();
+
+        let mut other = Session::new(Putt::new());
+        other.command("restore", path);
+        fs::remove_file(path).unwrap();
+        assert_eq!(other.lines, session.lines);
+        assert_eq!(other.putt.snapshot(), session.putt.snapshot());
+    }
+
+    #[test]
+    fn assert_save_moves_jumps() {
+        let path = env::temp_dir().join(format!("putt-save-{}.putt", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut session = Session::new(Putt::new());
+        session.putt.output = OutputPolicy::Nothing;
+        session.eval("9");
+        session.eval("3J 7 8");
+        assert_eq!(session.putt.stack, vec![Atom::Float(9.0), Atom::Float(8.0)]);
+        session.save(path, false).unwrap();
+
+        let saved = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(saved, "9\n4J 7 8\n");
+        let mut putt = Putt::new();
+        putt.parse(&saved).unwrap();
+        putt.eval_expression().unwrap();
+        assert_eq!(putt.stack, session.putt.stack);
+
+        let relocate = |line: &str, offset| relocate_jumps(line, &tokenize(line).unwrap(), offset);
+        assert_eq!(relocate("1XJ #tJ", 2), Ok("1 12J 3J".to_string()));
+        assert_eq!(relocate("1 1+J", 0), Ok("1 1+J".to_string()));
+        assert!(relocate("1 1+J", 2).is_err());
+    }
+
+    #[test]
+    fn assert_commands_are_not_code() {
+        assert_eq!(parse_command(" :save out.putt min"), Some(("save", "out.putt min")));
+        assert_eq!(parse_command(":stack"), Some(("stack", "")));
+        assert_eq!(parse_command(":d"), None);
+        assert_eq!(parse_command(":S"), None);
+        assert_eq!(parse_command("1 2:"), None);
+        for name in command_names() {
+            assert!(tokenize(&format!(":{}", name)).is_err(), "{}", name);
+        }
+
+        let mut session = Session::new(Putt::new());
+        session.putt.output = OutputPolicy::Nothing;
+        session.eval("1 3");
+        session.eval(":d");
+        let range = Atom::Arr(vec![Atom::Float(1.0), Atom::Float(2.0), Atom::Float(3.0)]);
+        assert_eq!(session.putt.stack, vec![range.clone(), range]);
+    }
+
+    fn replacements(line: &str) -> (usize, Vec<String>) {
+        let (start, pairs) = complete_at(line, line.len());
+        (start, pairs.into_iter().map(|p| p.replacement).collect())
+    }
+
+    #[test]
+    fn assert_continues_open_tokens() {
+        assert!(is_incomplete("1 \"a"));
+        assert!(is_incomplete("{\"a\" 1"));
+        assert!(is_incomplete("{\"a\" {1 2} \"b"));
+        assert!(!is_incomplete("{\"a\" 1}"));
+        assert!(!is_incomplete("{\"a\"}"));
+        assert!(!is_incomplete("{1 +"));
+    }
+
+    #[test]
+    fn assert_complete_builtins() {
+        assert_eq!(replacements("1 2c"), (3, vec!["c".to_string(), "cmp".to_string()]));
+        assert_eq!(replacements("1 2cm"), (3, vec!["cmp".to_string()]));
+        assert_eq!(replacements("X."), (1, vec![".f", ".c", ".r", ".l", ".p", ".i", ".j", ".J", ".g", ".s", ".d", ".k", ".v", ".h", ".m"].into_iter().map(String::from).collect()));
+        assert_eq!(replacements("1 2").1.len(), builtins::BUILTINS.len());
+    }
+
+    #[test]
+    fn assert_complete_commands() {
+        assert_eq!(replacements(":un"), (0, vec![":undo".to_string()]));
+        assert_eq!(replacements(":load fi"), (8, vec![]));
+        assert_eq!(replacements(":re"), (0, vec![":reset".to_string(), ":redo".to_string(), ":restore".to_string()]));
+    }
+}
@@ -0,0 +1,191 @@
+use super::*;
+use compiler::{Op, Program};
+use serde_json::{json, Value};
+
+/// Bumped whenever the format changes in a way older files can't be read with
+const VERSION: u64 = 1;
+
+/// `atom` tagged with its type, so nothing is lost on the way back
+fn atom_to_json(atom: &Atom) -> Value {
+    match atom {
+        // JSON has no infinity or NaN, Rust spells them `inf` and `NaN`
+        Atom::Float(f) if !f.is_finite() => json!({ "float": f.to_string() }),
+        Atom::Float(f) => json!({ "float": f }),
+        Atom::Str(st) => json!({ "str": st }),
+        Atom::Arr(arr) => json!({ "arr": arr.iter().map(atom_to_json).collect::<Vec<_>>() }),
+        Atom::Map(map) => json!({
+            "map": map.iter().map(|(k, v)| json!([atom_to_json(k), atom_to_json(v)])).collect::<Vec<_>>()
+        }),
+        Atom::BuiltIn(bi) => json!({ "builtin": bi.def().symbol }),
+    }
+}
+
+fn atom_from_json(value: &Value) -> Result<Atom, String> {
+    let bad = || format!("expected a value like {{\"float\": 1}}, got {}", value);
+    let (tag, inner) = match value.as_object() {
+        Some(obj) if obj.len() == 1 => obj.iter().next().unwrap(),
+        _ => return Err(bad()),
+    };
+
+    match (tag.as_str(), inner) {
+        ("float", Value::Number(n)) => n.as_f64().map(Atom::Float).ok_or_else(bad),
+        ("float", Value::String(st)) => st.parse().map(Atom::Float).map_err(|_| bad()),
+        ("str", Value::String(st)) => Ok(Atom::Str(st.clone())),
+        ("arr", Value::Array(arr)) => arr.iter().map(atom_from_json).collect::<Result<_, _>>().map(Atom::Arr),
+        ("map", Value::Array(entries)) => {
+            let mut map = Vec::new();
+            for entry in entries {
+                match entry.as_array().map(|a| a.as_slice()) {
+                    Some([k, v]) => atom::map_insert(&mut map, atom_from_json(k)?, atom_from_json(v)?),
+                    _ => return Err(bad()),
+                }
+            }
+            Ok(Atom::Map(map))
+        }
+        ("builtin", Value::String(symbol)) => builtins::lookup(symbol)
+            .map(|def| Atom::BuiltIn(def.builtin))
+            .ok_or_else(|| format!("no builtin {:?}", symbol)),
+        _ => Err(bad()),
+    }
+}
+
+fn op_to_json(op: &Op) -> Value {
+    match op {
+        Op::Push(n) => json!(["push", n]),
+        Op::Jump { value, target } => json!(["jump", value, target]),
+        Op::Call(bi) => json!(["call", bi.def().symbol]),
+    }
+}
+
+fn op_from_json(value: &Value) -> Result<Op, String> {
+    let index = |v: &Value| v.as_u64().map(|n| n as usize);
+    let op = match value.as_array().map(|a| a.as_slice()) {
+        Some([Value::String(kind), n]) if kind == "push" => index(n).map(Op::Push),
+        Some([Value::String(kind), v, t]) if kind == "jump" => match (index(v), index(t)) {
+            (Some(value), Some(target)) => Some(Op::Jump { value, target }),
+            _ => None,
+        },
+        Some([Value::String(kind), Value::String(symbol)]) if kind == "call" => {
+            builtins::lookup(symbol).map(|def| Op::Call(def.builtin))
+        }
+        _ => None,
+    };
+    op.ok_or_else(|| format!("bad instruction {}", value))
+}
+
+/// `snapshot` as JSON, to be written to a file and read back with `from_json`.
+/// Callers may add keys of their own, they are ignored when reading it back.
+pub fn to_json(snapshot: &Snapshot) -> Value {
+    json!({
+        "version": VERSION,
+        "stack": snapshot.stack.iter().map(atom_to_json).collect::<Vec<_>>(),
+        "consts": snapshot.code.consts.iter().map(atom_to_json).collect::<Vec<_>>(),
+        "ops": snapshot.code.ops.iter().map(op_to_json).collect::<Vec<_>>(),
+        "spans": snapshot.code.spans.iter().map(|s| json!([s.start, s.end])).collect::<Vec<_>>(),
+        "pc": snapshot.pc,
+        "chunk": snapshot.chunk,
+        "chunks": snapshot.chunks,
+    })
+}
+
+/// Read back a snapshot written by `to_json`, checking it can actually run
+pub fn from_json(value: &Value) -> Result<Snapshot, String> {
+    if value["version"].as_u64() != Some(VERSION) {
+        return Err(format!("not a version {} snapshot", VERSION));
+    }
+    let list = |key: &str| value[key].as_array().ok_or_else(|| format!("missing {:?}", key));
+    let index = |key: &str| value[key].as_u64().map(|n| n as usize).ok_or_else(|| format!("missing {:?}", key));
+
+    let stack = list("stack")?.iter().map(atom_from_json).collect::<Result<_, _>>()?;
+    let code = Program {
+        consts: list("consts")?.iter().map(atom_from_json).collect::<Result<_, _>>()?,
+        ops: list("ops")?.iter().map(op_from_json).collect::<Result<_, _>>()?,
+        spans: list("spans")?
+            .iter()
+            .map(|s| match (s[0].as_u64(), s[1].as_u64()) {
+                (Some(start), Some(end)) if start <= end => Ok(Span::new(start as usize, end as usize)),
+                _ => Err(format!("bad span {}", s)),
+            })
+            .collect::<Result<_, _>>()?,
+    };
+    let (pc, chunk) = (index("pc")?, index("chunk")?);
+    let chunks = list("chunks")?
+        .iter()
+        .map(|n| n.as_u64().map(|n| n as usize).ok_or_else(|| format!("bad chunk {}", n)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let len = code.ops.len();
+    let fits = |op: &Op| match *op {
+        Op::Push(n) => n < code.consts.len(),
+        Op::Jump { value, target } => value < code.consts.len() && target <= len,
+        Op::Call(_) => true,
+    };
+    // builtins count `pc` from the start of the chunk, which is the last one to start
+    let ordered = chunks.windows(2).all(|w| w[0] <= w[1]) && chunks.last().copied().unwrap_or(0) == chunk;
+    if code.spans.len() != len || pc > len || chunk > pc || !ordered || !code.ops.iter().all(fits) {
+        return Err("instructions don't fit together".to_string());
+    }
+
+    Ok(Snapshot { stack, inst: code.instructions(), spans: code.spans.clone(), code, pc, chunk, chunks })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assert_round_trips() {
+        let mut putt = Putt::new();
+        putt.parse("\"a\" 1 0/ 1 3: 9J 5").unwrap();
+        putt.eval_expression().unwrap();
+        putt.stack.push(Atom::Arr(vec![Atom::Arr(vec![]), Atom::BuiltIn(BuiltIn::Plus), Atom::Float(f64::NAN)]));
+        putt.stack.push(Atom::Map(vec![(Atom::Str("k".to_string()), Atom::Map(vec![]))]));
+
+        let snapshot = putt.snapshot();
+        let text = to_json(&snapshot).to_string();
+        let back = from_json(&serde_json::from_str(&text).unwrap()).unwrap();
+
+        // NaN is never equal to itself, so compare how it prints instead
+        assert_eq!(format!("{:?}", back), format!("{:?}", snapshot));
+    }
+
+    #[test]
+    fn assert_rejects_broken_files() {
+        let mut good = to_json(&Putt::new().snapshot());
+        assert!(from_json(&good).is_ok());
+        assert!(from_json(&json!({})).is_err());
+
+        good["ops"] = json!([["push", 0]]);
+        good["spans"] = json!([[0, 1]]);
+        assert_eq!(from_json(&good).unwrap_err(), "instructions don't fit together");
+        good["consts"] = json!([{ "float": 1 }]);
+        good["chunk"] = json!(1);
+        good["chunks"] = json!([1]);
+        good["pc"] = json!(0);
+        assert_eq!(from_json(&good).unwrap_err(), "instructions don't fit together");
+        good["chunk"] = json!(0);
+        assert_eq!(from_json(&good).unwrap_err(), "instructions don't fit together");
+        good["chunks"] = json!([0]);
+        assert!(from_json(&good).is_ok());
+        good["spans"] = json!([[1, 0]]);
+        assert_eq!(from_json(&good).unwrap_err(), "bad span [1,0]");
+        good["consts"] = json!([{ "builtin": "nope" }]);
+        assert_eq!(from_json(&good).unwrap_err(), "no builtin \"nope\"");
+    }
+
+    /// A program stopped by a limit carries on where it left off
+    #[test]
+    fn assert_resume() {
+        let src = "0 1+d9= X*1+J";
+        let mut putt = Putt::new();
+        putt.limits.max_instructions = Some(20);
+        putt.parse(src).unwrap();
+        assert_eq!(putt.eval_expression().unwrap_err().kind, RuntimeErrorKind::InstructionLimit(20));
+
+        let text = to_json(&putt.snapshot()).to_string();
+        let mut resumed = Putt::new();
+        resumed.restore(from_json(&serde_json::from_str(&text).unwrap()).unwrap());
+        resumed.resume().unwrap();
+        assert_eq!(resumed.stack.last(), Some(&Atom::Float(9.0)));
+    }
+}
@@ -1,3 +1,49 @@
-fn run_file(path: &str) {
+use putt::{host::BufferHost, Putt};
+use std::fs;
 
+/// Every example with the input it's given and everything it should print. `decomp`
+/// unpacks bytes that aren't valid smaz, so only the shape of its output is checked.
+const EXPECTED: &[(&str, &str, Option<&str>)] = &[
+    ("0pow0", "", Some("0 0^ is 1\n")),
+    ("decomp", "", None),
+    ("float", "", Some("1.09\n")),
+    ("hello_world", "", Some("Hello, World!\n")),
+    (
+        "math",
+        "",
+        Some("Math Rules!\nWe can do:\n\
+         Multiplication: (800 10*) ==  8000\n\
+         Division: (10 800/) ==  0.0125\n\
+         Addition: (909289 989898+) ==  909379\n\
+         Subtraction: (989898 1-) ==  -899898\n\
+         Modulus: (9900 989898%) ==  9900\n\
+         Exponents: (989898 2^) ==  979898050404\n\
+         Factorial: (30!) ==  265252859812191100000000000000000\n\
+         ROMAN NUMERALS: (CMD, X, Mk, IV, LkI) ==  1400 10 1000000 4 50001\n"),
+    ),
+    ("quine", "", Some("0\n")),
+    ("stdin", "42\n", Some("")),
+];
+
+fn run_file(path: &str, input: &str) -> String {
+    let src = fs::read_to_string(path).unwrap();
+    let mut putt = Putt::with_host(BufferHost::new(input));
+    putt.parse(&src).unwrap();
+    putt.eval_expression().unwrap();
+    putt.finish().unwrap();
+    putt.host.output
+}
+
+#[test]
+fn run_examples() {
+    for entry in fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_stem().unwrap().to_str().unwrap();
+        let (_, input, output) = EXPECTED.iter().find(|(n, _, _)| *n == name).unwrap_or_else(|| panic!("no expected output for {}", name));
+        let printed = run_file(path.to_str().unwrap(), input);
+        match output {
+            Some(output) => assert_eq!(printed, *output, "{}", name),
+            None => assert_eq!(printed.lines().count(), 1, "{}", name),
+        }
+    }
 }